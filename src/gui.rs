@@ -1,8 +1,14 @@
 use std::time::Duration;
 
 use egui::{Color32, RichText};
+use log::error;
 
-use crate::mediation::{ControllerValueMode, MediationDataModel, MONITOR_LOG_LENGTH};
+use crate::mediation::{
+    curves::{normalise, Curve},
+    messages::{ControllerLabel, MidiValue},
+    relative::RelativeEncoding,
+    ControllerValueMode, LearnTarget, MediationDataModel, MONITOR_LOG_LENGTH,
+};
 
 pub fn render_gui(model: &mut MediationDataModel, ui: &mut egui::Ui) {
     ui.heading("Tether Agent");
@@ -18,7 +24,8 @@ pub fn render_gui(model: &mut MediationDataModel, ui: &mut egui::Ui) {
 
     ui.heading("MIDI Ports Connected");
 
-    for (_key, info) in model.ports_metadata.iter() {
+    let mut reset_port = None;
+    for (key, info) in model.ports_metadata.iter_mut() {
         ui.horizontal(|ui| {
             ui.label(format!("PORT #{}: \"{}\"", info.index, info.full_name));
             if let Ok(elapsed) = info.last_received.elapsed() {
@@ -32,35 +39,56 @@ pub fn render_gui(model: &mut MediationDataModel, ui: &mut egui::Ui) {
                 ui.label(RichText::new(format!("{:.0}s ago", elapsed.as_secs_f32())).color(color));
             }
         });
+        ui.horizontal(|ui| {
+            ui.label("Controller value mode:");
+            ui.radio_value(
+                &mut info.controller_mode,
+                ControllerValueMode::Absolute,
+                "Absolute",
+            );
+            ui.radio_value(
+                &mut info.controller_mode,
+                ControllerValueMode::Relative,
+                "Relative",
+            );
+            if info.controller_mode == ControllerValueMode::Relative {
+                egui::ComboBox::from_id_source(format!("relative_encoding_{key}"))
+                    .selected_text(format!("{:?}", info.relative_encoding))
+                    .show_ui(ui, |ui| {
+                        for encoding in RelativeEncoding::ALL {
+                            ui.selectable_value(
+                                &mut info.relative_encoding,
+                                encoding,
+                                format!("{:?}", encoding),
+                            );
+                        }
+                    });
+            }
+            let remembered = model
+                .known_controller_values
+                .keys()
+                .filter(|(port, _, _)| *port == info.index)
+                .count();
+            ui.label(format!("{} controller values remembered", remembered));
+            if ui.button("Reset").clicked() {
+                reset_port = Some(info.index);
+            }
+        });
+    }
+    if let Some(port_index) = reset_port {
+        model.reset_controller_values(port_index);
     }
 
-    if !&model.knobs.is_empty() {
+    let mut port_indexes: Vec<usize> = model.ports_metadata.values().map(|i| i.index).collect();
+    port_indexes.sort();
+    for port_index in port_indexes {
         ui.separator();
-        ui.group(|ui| {
-            ui.label("Knob Mappings:");
-            let mut map_text = String::from("");
-            for (i, k) in model.knobs.iter().enumerate() {
-                map_text.push_str(&format!("#{}: {:?}  ", i, k.controller));
-            }
-            ui.small(map_text);
-        });
+        render_knob_editor(model, ui, port_index);
     }
 
     ui.separator();
 
-    ui.horizontal(|ui| {
-        ui.label("Controller value mode:");
-        ui.radio_value(
-            &mut model.controller_mode,
-            ControllerValueMode::Absolute,
-            "Absolute",
-        );
-        ui.radio_value(
-            &mut model.controller_mode,
-            ControllerValueMode::Relative,
-            "Relative",
-        );
-    });
+    ui.checkbox(&mut model.chord_detection, "Chord detection");
 
     ui.separator();
 
@@ -104,3 +132,283 @@ pub fn render_gui(model: &mut MediationDataModel, ui: &mut egui::Ui) {
         }
     });
 }
+
+enum KnobEdit {
+    MoveUp(usize),
+    MoveDown(usize),
+    Delete(usize),
+}
+
+/// List of knobs for one port, with editing, live preview and saving
+fn render_knob_editor(model: &mut MediationDataModel, ui: &mut egui::Ui, port_index: usize) {
+    let port_key = format!("{port_index}");
+    let Some(info) = model.ports_metadata.get(&port_key) else {
+        return;
+    };
+    let previews: Vec<Option<MidiValue>> = (0..info.knobs.len())
+        .map(|i| model.knob_value(port_index, i).cloned())
+        .collect();
+    let waiting: Vec<bool> = (0..info.knobs.len())
+        .map(|i| model.pickup_pending.contains_key(&(port_index, i)))
+        .collect();
+    let Some(info) = model.ports_metadata.get_mut(&port_key) else {
+        return;
+    };
+    let in_group: Vec<bool> = (0..info.knobs.len())
+        .map(|i| info.groups.iter().any(|group| group.has_knob(i)))
+        .collect();
+
+    let mut edit = None;
+    let mut save = false;
+    let mut export = false;
+    egui::CollapsingHeader::new(format!("Knob Mappings for \"{}\"", info.full_name))
+        .id_source(format!("knobs_{port_key}"))
+        .show(ui, |ui| {
+            egui::Grid::new(format!("knob_grid_{port_key}"))
+                .striped(true)
+                .show(ui, |ui| {
+                    for heading in [
+                        "#",
+                        "Name",
+                        "Controller",
+                        "Channel",
+                        "Min",
+                        "Max",
+                        "Invert",
+                        "Dead zones",
+                        "Curve",
+                        "Pickup",
+                        "Value",
+                        "",
+                    ] {
+                        ui.strong(heading);
+                    }
+                    ui.end_row();
+
+                    let count = info.knobs.len();
+                    for (i, (knob, preview)) in
+                        info.knobs.iter_mut().zip(previews.iter()).enumerate()
+                    {
+                        ui.label(format!("{}", i + info.knob_index_offset as usize));
+
+                        let mut name = knob.name.clone().unwrap_or_default();
+                        if ui
+                            .add(egui::TextEdit::singleline(&mut name).desired_width(120.))
+                            .changed()
+                        {
+                            knob.name = if name.is_empty() { None } else { Some(name) };
+                        }
+
+                        ui.label(match &knob.controller {
+                            ControllerLabel::Numbered(n) => format!("CC {n}"),
+                            ControllerLabel::Special(label) => label.clone(),
+                        });
+
+                        egui::ComboBox::from_id_source(format!("knob_channel_{port_key}_{i}"))
+                            .width(50.)
+                            .selected_text(match knob.channel {
+                                Some(c) => format!("{c}"),
+                                None => "Any".into(),
+                            })
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut knob.channel, None, "Any");
+                                for c in 1..=16 {
+                                    ui.selectable_value(&mut knob.channel, Some(c), format!("{c}"));
+                                }
+                            });
+
+                        let (mut min, mut max) = knob.range();
+                        if ui.add(egui::DragValue::new(&mut min).speed(0.01)).changed() {
+                            knob.min = Some(min);
+                        }
+                        if ui.add(egui::DragValue::new(&mut max).speed(0.01)).changed() {
+                            knob.max = Some(max);
+                        }
+
+                        ui.checkbox(&mut knob.invert, "");
+
+                        ui.horizontal(|ui| {
+                            let mut low = knob.dead_zone_low.unwrap_or(0.0);
+                            let mut high = knob.dead_zone_high.unwrap_or(0.0);
+                            if ui.add(dead_zone_value(&mut low)).changed() {
+                                knob.dead_zone_low = Some(low);
+                            }
+                            if ui.add(dead_zone_value(&mut high)).changed() {
+                                knob.dead_zone_high = Some(high);
+                            }
+                        });
+
+                        let mut curve = knob.curve.unwrap_or_default();
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_id_source(format!("knob_curve_{port_key}_{i}"))
+                                .selected_text(curve.name())
+                                .show_ui(ui, |ui| {
+                                    for option in Curve::ALL {
+                                        // Keep the number of steps when re-selecting Stepped
+                                        let selected = curve.name() == option.name();
+                                        if ui.selectable_label(selected, option.name()).clicked()
+                                            && !selected
+                                        {
+                                            curve = option;
+                                        }
+                                    }
+                                });
+                            if let Curve::Stepped(steps) = &mut curve {
+                                ui.add(egui::DragValue::new(steps).clamp_range(2..=127));
+                            }
+                        });
+                        if curve != knob.curve.unwrap_or_default() {
+                            knob.curve = Some(curve);
+                        }
+
+                        ui.checkbox(&mut knob.pickup, "");
+
+                        match preview {
+                            Some(_) if waiting[i] => {
+                                ui.label(
+                                    RichText::new("waiting for pickup").color(Color32::YELLOW),
+                                );
+                            }
+                            Some(value) => {
+                                ui.add(
+                                    egui::ProgressBar::new(normalise(value))
+                                        .desired_width(100.)
+                                        .text(format!("{:.3}", knob.output(value))),
+                                );
+                            }
+                            None => {
+                                ui.label("-");
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                                edit = Some(KnobEdit::MoveUp(i));
+                            }
+                            if ui
+                                .add_enabled(i + 1 < count, egui::Button::new("Down"))
+                                .clicked()
+                            {
+                                edit = Some(KnobEdit::MoveDown(i));
+                            }
+                            // Groups would be left without the knob
+                            if ui
+                                .add_enabled(!in_group[i], egui::Button::new("Delete"))
+                                .on_disabled_hover_text("Part of a group")
+                                .clicked()
+                            {
+                                edit = Some(KnobEdit::Delete(i));
+                            }
+                            learn_button(ui, &mut model.learning, port_index, i);
+                        });
+                        ui.end_row();
+                    }
+                });
+
+            if !info.buttons.is_empty() {
+                let latched = model.button_states.get(&port_index);
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Buttons:");
+                    for (i, button) in info.buttons.iter().enumerate() {
+                        let on = latched.and_then(|l| l.get(&i)).copied().unwrap_or(false);
+                        let text = format!(
+                            "#{} {} ({:?})",
+                            i,
+                            button.name.as_deref().unwrap_or(""),
+                            button.behaviour
+                        );
+                        ui.label(RichText::new(text).color(if on {
+                            Color32::GREEN
+                        } else {
+                            Color32::GRAY
+                        }));
+                    }
+                });
+            }
+
+            ui.horizontal(|ui| {
+                if learn_button(ui, &mut model.learning, port_index, info.knobs.len()) {
+                    ui.label("Move a control...");
+                } else {
+                    ui.label("Learn new knob");
+                }
+                save = ui.button("Save mapping").clicked();
+                export = ui.button("Copy JSON").clicked();
+            });
+        });
+
+    if edit.is_some() {
+        model
+            .pickup_pending
+            .retain(|(port, _), _| *port != port_index);
+        model
+            .knob_outputs
+            .retain(|(port, _), _| *port != port_index);
+        model
+            .knob_channels
+            .retain(|(port, _), _| *port != port_index);
+    }
+    match edit {
+        Some(KnobEdit::MoveUp(i)) => {
+            info.knobs.swap(i - 1, i);
+            for group in info.groups.iter_mut() {
+                group.swap_knobs(i - 1, i);
+            }
+        }
+        Some(KnobEdit::MoveDown(i)) => {
+            info.knobs.swap(i, i + 1);
+            for group in info.groups.iter_mut() {
+                group.swap_knobs(i, i + 1);
+            }
+        }
+        Some(KnobEdit::Delete(i)) => {
+            info.knobs.remove(i);
+            for group in info.groups.iter_mut() {
+                group.remove_knob(i);
+            }
+        }
+        None => {}
+    }
+
+    if save {
+        if let Err(e) = model.save_knob_mapping(port_index) {
+            error!("Failed to save knob mapping: {}", e);
+        }
+    }
+    if export {
+        match model
+            .current_device_mapping(port_index)
+            .and_then(|device| Ok(serde_json::to_string_pretty(&device)?))
+        {
+            Ok(json) => ui.output_mut(|o| o.copied_text = json),
+            Err(e) => error!("Failed to export knob mapping: {}", e),
+        }
+    }
+}
+
+fn dead_zone_value(value: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .speed(0.005)
+        .clamp_range(0.0..=0.5)
+}
+
+/// Toggles MIDI learn for this knob; returns true if it is waiting to learn
+fn learn_button(
+    ui: &mut egui::Ui,
+    learning: &mut Option<LearnTarget>,
+    port_index: usize,
+    knob_index: usize,
+) -> bool {
+    let target = LearnTarget {
+        port_index,
+        knob_index,
+    };
+    let is_learning = *learning == Some(target);
+    if ui
+        .selectable_label(is_learning, if is_learning { "Cancel" } else { "Learn" })
+        .clicked()
+    {
+        *learning = if is_learning { None } else { Some(target) };
+    }
+    is_learning
+}
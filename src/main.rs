@@ -5,10 +5,17 @@ use eframe::egui;
 use egui::Vec2;
 use env_logger::Env;
 use gui::render_gui;
-use log::{debug, info, warn};
-use mediation::{ControllerValueMode, MediationDataModel};
-use midi_interface::{get_midi_connection, midi_listener_thread};
-use midir::{Ignore, MidiInput};
+use log::{debug, error, info, warn};
+use mediation::{
+    mappings::{load_all_mappings, watch_mappings},
+    notes::NoteNaming,
+    velocity::load_note_profiles,
+    ControllerValueMode, MediationDataModel,
+};
+use midi_interface::{
+    get_midi_connection, get_midi_output_connection, midi_listener_thread, midi_sender_thread,
+};
+use midir::{Ignore, MidiInput, MidiOutput, MidiOutputConnection};
 use settings::Cli;
 use tether_interface::{start_tether_agent, TetherSettings};
 
@@ -35,6 +42,46 @@ fn list_midi_ports() -> anyhow::Result<Vec<usize>> {
     }
     Ok(port_indexes)
 }
+
+fn list_midi_output_ports() {
+    let midi_output = MidiOutput::new("midir writing output").expect("midir failure");
+    for (i, p) in midi_output.ports().iter().enumerate() {
+        info!(
+            "Available MIDI output port: #{} = {}",
+            i,
+            midi_output
+                .port_name(p)
+                .expect("failed to retrieve port name")
+        );
+    }
+}
+
+fn open_midi_output(cli: &Cli) -> Option<MidiOutputConnection> {
+    if let Some(port) = &cli.midi_output {
+        let midi_output = MidiOutput::new("midir writing output").expect("midir failure");
+        let (connection, port_name) =
+            get_midi_output_connection(midi_output, port).expect("failed to open MIDI output");
+        info!("Will send MIDI to output port \"{}\"", port_name);
+        return Some(connection);
+    }
+    if let Some(port_name) = &cli.midi_virtual_output {
+        #[cfg(unix)]
+        {
+            let midi_output = MidiOutput::new("midir writing output").expect("midir failure");
+            let connection = midi_interface::create_virtual_midi_output(midi_output, port_name)
+                .expect("failed to create virtual MIDI output");
+            info!("Will send MIDI to virtual output port \"{}\"", port_name);
+            return Some(connection);
+        }
+        #[cfg(not(unix))]
+        warn!(
+            "Virtual MIDI output \"{}\" is not supported on this platform",
+            port_name
+        );
+    }
+    None
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -43,6 +90,8 @@ fn main() {
         .filter_module("tether_agent", log::LevelFilter::Warn)
         .init();
     let available_port_indexes = list_midi_ports().expect("failed to list MIDI ports");
+    list_midi_output_ports();
+    let midi_output_connection = open_midi_output(&cli);
 
     let listen_ports = if cli.midi_ports.is_empty() {
         warn!("No ports specified; will listen on all available MIDI Inputs");
@@ -59,6 +108,13 @@ fn main() {
     let (midi_tx, midi_rx) = mpsc::channel();
     let (tether_tx, tether_rx) = mpsc::channel();
     let (tether_state_tx, tether_state_rx) = mpsc::channel();
+    let (tether_command_tx, tether_command_rx) = mpsc::channel();
+
+    let midi_out_tx = midi_output_connection.map(|connection| {
+        let (midi_out_tx, midi_out_rx) = mpsc::channel();
+        handles.push(midi_sender_thread(connection, midi_out_rx));
+        midi_out_tx
+    });
 
     let tether_settings = TetherSettings {
         host: cli.tether_host,
@@ -73,6 +129,8 @@ fn main() {
             tether_rx,
             tether_state_tx,
             tether_settings,
+            midi_out_tx,
+            tether_command_tx,
         ));
     }
 
@@ -80,13 +138,32 @@ fn main() {
         midi_rx,
         tether_tx,
         tether_state_rx,
-        if cli.relative_mode_enabled {
-            ControllerValueMode::Relative
-        } else {
-            ControllerValueMode::Absolute
+        tether_command_rx,
+        NoteNaming {
+            middle_c_octave: cli.middle_c_octave,
+            a4_frequency: cli.a4_frequency,
         },
     );
 
+    model.chord_detection = cli.chords_enabled;
+    model.continuous_knobs = cli.knobs_continuous;
+    if let Some(path) = &cli.notes_profile {
+        match load_note_profiles(path) {
+            Ok(profiles) => {
+                info!("Loaded {} note profile(s)", profiles.len());
+                model.note_profiles = profiles;
+            }
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    if !cli.knobs_disable {
+        model.device_mappings = load_all_mappings(cli.mappings.as_deref());
+        let (mappings_tx, mappings_rx) = mpsc::channel();
+        handles.push(watch_mappings(cli.mappings.clone(), mappings_tx));
+        model.mappings_rx = Some(mappings_rx);
+    }
+    model.mappings_save_path = cli.mappings.clone();
+
     for port in listen_ports {
         let mut midi_input = MidiInput::new("midir reading input").expect("midir failure");
         midi_input.ignore(Ignore::None);
@@ -94,9 +171,14 @@ fn main() {
         let midi_tx = midi_tx.clone();
         let (midi_input_port, port_name) =
             get_midi_connection(&midi_input, port).expect("failed to open MIDI port");
-        model.add_port(port, port_name.clone());
+        let controller_mode = if cli.relative_mode_enabled || cli.relative_ports.contains(&port) {
+            ControllerValueMode::Relative
+        } else {
+            ControllerValueMode::Absolute
+        };
+        model.add_port(port, port_name.clone(), controller_mode);
         if !cli.knobs_disable {
-            match model.add_knob_mapping(&port_name) {
+            match model.add_knob_mapping(port, &port_name) {
                 Ok(_) => info!(
                     "Added automatic knob mapping for device \"{}\" OK",
                     &port_name
@@ -119,6 +201,9 @@ fn main() {
                 debug!("Last received message: {:?}", &msg);
                 model.handle_incoming_midi(*port_index, msg);
             }
+            model.handle_tether_commands();
+            model.handle_mapping_updates();
+            model.update_knob_outputs();
             std::thread::sleep(Duration::from_millis(1));
         }
     } else {
@@ -152,10 +237,14 @@ impl eframe::App for MediationDataModel {
             self.tether_uri = broker_uri.clone();
         }
 
+        self.handle_tether_commands();
+        self.handle_mapping_updates();
+
         while let Ok((port_index, msg)) = &self.midi_rx.try_recv() {
             debug!("GUI received MIDI message: {:?}", msg);
             self.handle_incoming_midi(*port_index, msg);
             // std::thread::sleep(Duration::from_millis(1));
         }
+        self.update_knob_outputs();
     }
 }
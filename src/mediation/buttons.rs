@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{mappings::filters_match, messages::ControllerLabel};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum ButtonBehaviour {
+    /// On while held down
+    #[default]
+    Momentary,
+    /// Each press flips the state, which is kept by the mediator
+    Toggle,
+    /// Pressing turns this button on and every other button in the same
+    /// (named) group on the device off
+    Radio(String),
+}
+
+/// A button or pad, sending either a Control Change (0 = released, anything
+/// else = pressed) or notes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ButtonMapping {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Only match this MIDI channel (1-16)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+    /// Only match ports whose name contains this text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub controller: Option<ControllerLabel>,
+    /// MIDI note number, for pads that send notes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<u8>,
+    #[serde(default)]
+    pub behaviour: ButtonBehaviour,
+}
+
+/// What a button press arrived as
+pub enum ButtonInput<'a> {
+    Controller(&'a ControllerLabel),
+    Note(u8),
+}
+
+impl ButtonMapping {
+    pub fn matches(&self, port_name: &str, channel: u8, input: &ButtonInput) -> bool {
+        if !filters_match(self.channel, self.port.as_deref(), port_name, channel) {
+            return false;
+        }
+        match input {
+            ButtonInput::Controller(label) => self.controller.as_ref() == Some(*label),
+            ButtonInput::Note(note) => self.note == Some(*note),
+        }
+    }
+}
+
+/// Apply a press or release of button `index`, updating the latched states
+/// (by button index); returns (index, pressed, state) for every button that
+/// should be published
+pub fn press_button(
+    buttons: &[ButtonMapping],
+    latched: &mut HashMap<usize, bool>,
+    index: usize,
+    pressed: bool,
+) -> Vec<(usize, bool, bool)> {
+    let Some(button) = buttons.get(index) else {
+        return Vec::new();
+    };
+    match &button.behaviour {
+        ButtonBehaviour::Momentary => vec![(index, pressed, pressed)],
+        ButtonBehaviour::Toggle => {
+            let state = latched.entry(index).or_default();
+            if pressed {
+                *state = !*state;
+            }
+            vec![(index, pressed, *state)]
+        }
+        ButtonBehaviour::Radio(group) => {
+            let mut changes = Vec::new();
+            if pressed {
+                for (other, other_button) in buttons.iter().enumerate() {
+                    let in_group = other_button.behaviour == ButtonBehaviour::Radio(group.clone());
+                    if other != index && in_group && latched.get(&other) == Some(&true) {
+                        latched.insert(other, false);
+                        changes.push((other, false, false));
+                    }
+                }
+                latched.insert(index, true);
+            }
+            changes.push((index, pressed, latched.get(&index) == Some(&true)));
+            changes
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn button(note: u8, behaviour: ButtonBehaviour) -> ButtonMapping {
+        ButtonMapping {
+            name: None,
+            channel: None,
+            port: None,
+            controller: None,
+            note: Some(note),
+            behaviour,
+        }
+    }
+
+    #[test]
+    fn momentary_follows_the_button() {
+        let buttons = vec![button(36, ButtonBehaviour::Momentary)];
+        let mut latched = HashMap::new();
+        assert_eq!(
+            press_button(&buttons, &mut latched, 0, true),
+            vec![(0, true, true)]
+        );
+        assert_eq!(
+            press_button(&buttons, &mut latched, 0, false),
+            vec![(0, false, false)]
+        );
+        assert!(press_button(&buttons, &mut latched, 1, true).is_empty());
+    }
+
+    #[test]
+    fn toggle_flips_on_each_press() {
+        let buttons = vec![button(36, ButtonBehaviour::Toggle)];
+        let mut latched = HashMap::new();
+        let table = [
+            (true, true),
+            (false, true),
+            (true, false),
+            (false, false),
+            (true, true),
+        ];
+        for (pressed, state) in table {
+            assert_eq!(
+                press_button(&buttons, &mut latched, 0, pressed),
+                vec![(0, pressed, state)]
+            );
+        }
+    }
+
+    #[test]
+    fn radio_turns_off_the_rest_of_its_group() {
+        let radio = |group: &str| ButtonBehaviour::Radio(group.into());
+        let buttons = vec![
+            button(36, radio("a")),
+            button(37, radio("a")),
+            button(38, radio("b")),
+            button(39, ButtonBehaviour::Toggle),
+        ];
+        let mut latched = HashMap::new();
+        press_button(&buttons, &mut latched, 2, true);
+        press_button(&buttons, &mut latched, 3, true);
+        assert_eq!(
+            press_button(&buttons, &mut latched, 0, true),
+            vec![(0, true, true)]
+        );
+        assert_eq!(
+            press_button(&buttons, &mut latched, 0, false),
+            vec![(0, false, true)]
+        );
+        assert_eq!(
+            press_button(&buttons, &mut latched, 1, true),
+            vec![(0, false, false), (1, true, true)]
+        );
+        // Pressing the button that is already on leaves it on
+        assert_eq!(
+            press_button(&buttons, &mut latched, 1, true),
+            vec![(1, true, true)]
+        );
+        // Other groups, and buttons outside any group, are left alone
+        assert_eq!(latched.get(&2), Some(&true));
+        assert_eq!(latched.get(&3), Some(&true));
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use super::notes::pitch_class_name;
+
+/// Intervals (semitones above the root, stacked in thirds so that the
+/// position of the bass gives the inversion), suffix used in the chord name,
+/// and descriptive quality
+const CHORD_TEMPLATES: &[(&[u8], &str, &str)] = &[
+    (&[0, 4, 7], "", "major"),
+    (&[0, 3, 7], "m", "minor"),
+    (&[0, 3, 6], "dim", "diminished"),
+    (&[0, 4, 8], "aug", "augmented"),
+    (&[0, 2, 7], "sus2", "sus2"),
+    (&[0, 5, 7], "sus4", "sus4"),
+    (&[0, 7], "5", "power"),
+    (&[0, 4, 7, 10], "7", "dominant7"),
+    (&[0, 4, 7, 11], "maj7", "major7"),
+    (&[0, 3, 7, 10], "m7", "minor7"),
+    (&[0, 3, 7, 11], "mMaj7", "minorMajor7"),
+    (&[0, 3, 6, 9], "dim7", "diminished7"),
+    (&[0, 3, 6, 10], "m7b5", "halfDiminished7"),
+    (&[0, 4, 8, 10], "aug7", "augmented7"),
+    (&[0, 5, 7, 10], "7sus4", "dominant7sus4"),
+    (&[0, 4, 7, 9], "6", "major6"),
+    (&[0, 3, 7, 9], "m6", "minor6"),
+    (&[0, 4, 7, 2], "add9", "add9"),
+    (&[0, 3, 7, 2], "madd9", "minorAdd9"),
+    (&[0, 4, 7, 10, 2], "9", "dominant9"),
+    (&[0, 4, 7, 11, 2], "maj9", "major9"),
+    (&[0, 3, 7, 10, 2], "m9", "minor9"),
+];
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Chord {
+    /// Full chord symbol, e.g. "Am7/G"
+    pub name: String,
+    /// e.g. "A"
+    pub root: String,
+    /// e.g. "minor7"
+    pub quality: String,
+    /// Lowest sounding note, e.g. "G"
+    pub bass: String,
+    /// 0 for root position, 1 for first inversion (third in the bass), etc.
+    pub inversion: u8,
+}
+
+/// Name the chord formed by these MIDI notes, if they form a known chord
+pub fn recognise_chord(notes: &[u8]) -> Option<Chord> {
+    let bass_note = *notes.iter().min()?;
+    let bass = bass_note % 12;
+    let pitch_classes: BTreeSet<u8> = notes.iter().map(|n| n % 12).collect();
+
+    // A root in the bass wins (so C-E-G-A is C6 rather than Am7/C); otherwise
+    // the earliest template wins (so G-A-C-E is Am7/G rather than C6/G)
+    let (root, (template, suffix, quality)) = pitch_classes
+        .iter()
+        .flat_map(|root| {
+            let intervals: BTreeSet<u8> = pitch_classes
+                .iter()
+                .map(|pc| (pc + 12 - root) % 12)
+                .collect();
+            CHORD_TEMPLATES
+                .iter()
+                .enumerate()
+                .filter(move |(_, (template, _, _))| {
+                    template.iter().copied().collect::<BTreeSet<u8>>() == intervals
+                })
+                .map(move |(priority, template)| (*root, priority, template))
+        })
+        .min_by_key(|(root, priority, _)| (*root != bass, *priority))
+        .map(|(root, _, template)| (root, template))?;
+
+    let bass_interval = (bass + 12 - root) % 12;
+    let inversion = template
+        .iter()
+        .position(|i| *i == bass_interval)
+        .unwrap_or(0) as u8;
+    let mut name = format!("{}{}", pitch_class_name(root), suffix);
+    if bass != root {
+        name.push('/');
+        name.push_str(pitch_class_name(bass));
+    }
+    Some(Chord {
+        name,
+        root: pitch_class_name(root).into(),
+        quality: (*quality).into(),
+        bass: pitch_class_name(bass).into(),
+        inversion,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(notes: &[u8]) -> Option<String> {
+        recognise_chord(notes).map(|chord| chord.name)
+    }
+
+    #[test]
+    fn root_position() {
+        let chord = recognise_chord(&[60, 64, 67]).unwrap();
+        assert_eq!(chord.name, "C");
+        assert_eq!(chord.root, "C");
+        assert_eq!(chord.quality, "major");
+        assert_eq!(chord.bass, "C");
+        assert_eq!(chord.inversion, 0);
+        assert_eq!(name(&[57, 60, 64]).as_deref(), Some("Am"));
+    }
+
+    #[test]
+    fn inversions() {
+        let first = recognise_chord(&[64, 67, 72]).unwrap();
+        assert_eq!((first.name.as_str(), first.inversion), ("C/E", 1));
+        let second = recognise_chord(&[67, 72, 76]).unwrap();
+        assert_eq!((second.name.as_str(), second.inversion), ("C/G", 2));
+        let third = recognise_chord(&[55, 57, 60, 64]).unwrap();
+        assert_eq!((third.name.as_str(), third.inversion), ("Am7/G", 3));
+    }
+
+    #[test]
+    fn slash_chords_prefer_the_root_in_the_bass() {
+        assert_eq!(name(&[60, 64, 67, 69]).as_deref(), Some("C6"));
+        assert_eq!(name(&[57, 64, 67, 72]).as_deref(), Some("Am7"));
+        // Doubled notes and wide voicings don't matter
+        assert_eq!(name(&[40, 60, 67, 72, 76]).as_deref(), Some("C/E"));
+    }
+
+    #[test]
+    fn diminished_seventh_and_suspended() {
+        let dim7 = recognise_chord(&[60, 63, 66, 69]).unwrap();
+        assert_eq!(dim7.quality, "diminished7");
+        assert_eq!(dim7.root, "C");
+        assert_eq!(dim7.inversion, 0);
+        assert_eq!(name(&[60, 65, 67]).as_deref(), Some("Csus4"));
+        assert_eq!(name(&[53, 60, 67]).as_deref(), Some("Fsus2"));
+        assert_eq!(name(&[60, 65, 67, 70]).as_deref(), Some("C7sus4"));
+    }
+
+    #[test]
+    fn unknown_chords() {
+        assert_eq!(name(&[]), None);
+        assert_eq!(name(&[60]), None);
+        assert_eq!(name(&[60, 61, 62]), None);
+    }
+}
@@ -0,0 +1,762 @@
+use std::collections::HashMap;
+
+use midi_msg::{ChannelModeMsg, ControlChange, PolyMode};
+use serde::{Deserialize, Serialize};
+
+use super::messages::{ControllerLabel, MidiValue};
+
+/// Label and value for any Control Change message, as published on the
+/// "controlChange" plug. Only `Parameter` (RPN/NRPN) is not covered here,
+/// since it is not a single controller value.
+pub fn label_control_change(control: &ControlChange) -> Option<(ControllerLabel, MidiValue)> {
+    use ControlChange::*;
+    use MidiValue::{HighRes, LowRes, Switch};
+
+    let special = |label: &str| ControllerLabel::Special(label.into());
+
+    let labelled = match *control {
+        Undefined { control, value } => (ControllerLabel::Numbered(control), LowRes(value)),
+        UndefinedHighRes {
+            control1,
+            control2,
+            value,
+        } => (
+            special(&format!("UndefinedHighRes-{}-{}", control1, control2)),
+            HighRes(value),
+        ),
+
+        // 14-bit controllers
+        BankSelect(x) => (special("BankSelect"), HighRes(x)),
+        ModWheel(x) => (special("ModWheel"), HighRes(x)),
+        Breath(x) => (special("Breath"), HighRes(x)),
+        Foot(x) => (special("Foot"), HighRes(x)),
+        Portamento(x) => (special("Portamento"), HighRes(x)),
+        Volume(x) => (special("Volume"), HighRes(x)),
+        Balance(x) => (special("Balance"), HighRes(x)),
+        Pan(x) => (special("Pan"), HighRes(x)),
+        Expression(x) => (special("Expression"), HighRes(x)),
+        Effect1(x) => (special("Effect1"), HighRes(x)),
+        Effect2(x) => (special("Effect2"), HighRes(x)),
+        GeneralPurpose1(x) => (special("GeneralPurpose1"), HighRes(x)),
+        GeneralPurpose2(x) => (special("GeneralPurpose2"), HighRes(x)),
+        GeneralPurpose3(x) => (special("GeneralPurpose3"), HighRes(x)),
+        GeneralPurpose4(x) => (special("GeneralPurpose4"), HighRes(x)),
+        DataEntry(x) => (special("DataEntry"), HighRes(x)),
+        DataEntry2(msb, lsb) => (
+            special("DataEntry"),
+            HighRes(((msb as u16) << 7) | lsb as u16),
+        ),
+
+        // Switches (CC 64-69), on at 64 and above
+        Hold(x) => (special("Hold"), Switch(x >= 64)),
+        TogglePortamento(on) => (special("TogglePortamento"), Switch(on)),
+        Sostenuto(x) => (special("Sostenuto"), Switch(x >= 64)),
+        SoftPedal(x) => (special("SoftPedal"), Switch(x >= 64)),
+        ToggleLegato(on) => (special("ToggleLegato"), Switch(on)),
+        Hold2(x) => (special("Hold2"), Switch(x >= 64)),
+
+        // 7-bit controllers
+        SoundControl1(x) => (special("SoundControl1"), LowRes(x)),
+        SoundControl2(x) => (special("SoundControl2"), LowRes(x)),
+        SoundControl3(x) => (special("SoundControl3"), LowRes(x)),
+        SoundControl4(x) => (special("SoundControl4"), LowRes(x)),
+        SoundControl5(x) => (special("SoundControl5"), LowRes(x)),
+        SoundControl6(x) => (special("SoundControl6"), LowRes(x)),
+        SoundControl7(x) => (special("SoundControl7"), LowRes(x)),
+        SoundControl8(x) => (special("SoundControl8"), LowRes(x)),
+        SoundControl9(x) => (special("SoundControl9"), LowRes(x)),
+        SoundControl10(x) => (special("SoundControl10"), LowRes(x)),
+        SoundVariation(x) => (special("SoundVariation"), LowRes(x)),
+        Timbre(x) => (special("Timbre"), LowRes(x)),
+        ReleaseTime(x) => (special("ReleaseTime"), LowRes(x)),
+        AttackTime(x) => (special("AttackTime"), LowRes(x)),
+        Brightness(x) => (special("Brightness"), LowRes(x)),
+        DecayTime(x) => (special("DecayTime"), LowRes(x)),
+        VibratoRate(x) => (special("VibratoRate"), LowRes(x)),
+        VibratoDepth(x) => (special("VibratoDepth"), LowRes(x)),
+        VibratoDelay(x) => (special("VibratoDelay"), LowRes(x)),
+        GeneralPurpose5(x) => (special("GeneralPurpose5"), LowRes(x)),
+        GeneralPurpose6(x) => (special("GeneralPurpose6"), LowRes(x)),
+        GeneralPurpose7(x) => (special("GeneralPurpose7"), LowRes(x)),
+        GeneralPurpose8(x) => (special("GeneralPurpose8"), LowRes(x)),
+        PortamentoControl(x) => (special("PortamentoControl"), LowRes(x)),
+        HighResVelocity(x) => (special("HighResVelocity"), LowRes(x)),
+        Effects1Depth(x) => (special("Effects1Depth"), LowRes(x)),
+        Effects2Depth(x) => (special("Effects2Depth"), LowRes(x)),
+        Effects3Depth(x) => (special("Effects3Depth"), LowRes(x)),
+        Effects4Depth(x) => (special("Effects4Depth"), LowRes(x)),
+        Effects5Depth(x) => (special("Effects5Depth"), LowRes(x)),
+        ReverbSendLevel(x) => (special("ReverbSendLevel"), LowRes(x)),
+        TremoloDepth(x) => (special("TremoloDepth"), LowRes(x)),
+        ChorusSendLevel(x) => (special("ChorusSendLevel"), LowRes(x)),
+        CelesteDepth(x) => (special("CelesteDepth"), LowRes(x)),
+        PhaserDepth(x) => (special("PhaserDepth"), LowRes(x)),
+        DataIncrement(x) => (special("DataIncrement"), LowRes(x)),
+        DataDecrement(x) => (special("DataDecrement"), LowRes(x)),
+
+        Parameter(_) => return None,
+    };
+    Some(labelled)
+}
+
+/// Channel Mode messages (CC 120-127) are published as if they were
+/// ordinary controllers; those without a value are sent as `Switch(true)`
+pub fn label_channel_mode(msg: &ChannelModeMsg) -> (ControllerLabel, MidiValue) {
+    let special = |label: &str| ControllerLabel::Special(label.into());
+
+    match *msg {
+        ChannelModeMsg::AllSoundOff => (special("AllSoundOff"), MidiValue::Switch(true)),
+        ChannelModeMsg::ResetAllControllers => {
+            (special("ResetAllControllers"), MidiValue::Switch(true))
+        }
+        ChannelModeMsg::LocalControl(on) => (special("LocalControl"), MidiValue::Switch(on)),
+        ChannelModeMsg::AllNotesOff => (special("AllNotesOff"), MidiValue::Switch(true)),
+        ChannelModeMsg::OmniMode(on) => (special("OmniMode"), MidiValue::Switch(on)),
+        ChannelModeMsg::PolyMode(PolyMode::Mono(channels)) => {
+            (special("MonoMode"), MidiValue::LowRes(channels))
+        }
+        ChannelModeMsg::PolyMode(PolyMode::Poly) => (special("PolyMode"), MidiValue::Switch(true)),
+    }
+}
+
+/// Reverse of `label_control_change`, for `ControllerLabel::Special` labels.
+/// The value is converted to whatever resolution the controller expects.
+pub fn control_change_from_label(label: &str, value: &MidiValue) -> Option<ControlChange> {
+    use ControlChange::*;
+
+    if let Some(controls) = label.strip_prefix("UndefinedHighRes-") {
+        let (control1, control2) = controls.split_once('-')?;
+        return Some(UndefinedHighRes {
+            control1: control1.parse().ok()?,
+            control2: control2.parse().ok()?,
+            value: value_as_u14(value),
+        });
+    }
+
+    let x14 = value_as_u14(value);
+    let x7 = value_as_u7(value);
+    let on = value_as_switch(value);
+
+    let control = match label {
+        "BankSelect" => BankSelect(x14),
+        "ModWheel" => ModWheel(x14),
+        "Breath" => Breath(x14),
+        "Foot" => Foot(x14),
+        "Portamento" => Portamento(x14),
+        "Volume" => Volume(x14),
+        "Balance" => Balance(x14),
+        "Pan" => Pan(x14),
+        "Expression" => Expression(x14),
+        "Effect1" => Effect1(x14),
+        "Effect2" => Effect2(x14),
+        // midi-msg serialises GeneralPurpose1-4 onto CC 0, so spell out CC 16-19 instead
+        "GeneralPurpose1" => high_res_numbered(16, x14),
+        "GeneralPurpose2" => high_res_numbered(17, x14),
+        "GeneralPurpose3" => high_res_numbered(18, x14),
+        "GeneralPurpose4" => high_res_numbered(19, x14),
+        "DataEntry" => DataEntry(x14),
+
+        "Hold" => Hold(if on { 127 } else { 0 }),
+        "TogglePortamento" => TogglePortamento(on),
+        "Sostenuto" => Sostenuto(if on { 127 } else { 0 }),
+        "SoftPedal" => SoftPedal(if on { 127 } else { 0 }),
+        "ToggleLegato" => ToggleLegato(on),
+        "Hold2" => Hold2(if on { 127 } else { 0 }),
+
+        "SoundControl1" => SoundControl1(x7),
+        "SoundControl2" => SoundControl2(x7),
+        "SoundControl3" => SoundControl3(x7),
+        "SoundControl4" => SoundControl4(x7),
+        "SoundControl5" => SoundControl5(x7),
+        "SoundControl6" => SoundControl6(x7),
+        "SoundControl7" => SoundControl7(x7),
+        "SoundControl8" => SoundControl8(x7),
+        "SoundControl9" => SoundControl9(x7),
+        "SoundControl10" => SoundControl10(x7),
+        "SoundVariation" => SoundVariation(x7),
+        "Timbre" => Timbre(x7),
+        "ReleaseTime" => ReleaseTime(x7),
+        "AttackTime" => AttackTime(x7),
+        "Brightness" => Brightness(x7),
+        "DecayTime" => DecayTime(x7),
+        "VibratoRate" => VibratoRate(x7),
+        "VibratoDepth" => VibratoDepth(x7),
+        "VibratoDelay" => VibratoDelay(x7),
+        "GeneralPurpose5" => GeneralPurpose5(x7),
+        // ...and GeneralPurpose6-8 onto CC 82-84, rather than CC 81-83
+        "GeneralPurpose6" => Undefined {
+            control: 81,
+            value: x7,
+        },
+        "GeneralPurpose7" => Undefined {
+            control: 82,
+            value: x7,
+        },
+        "GeneralPurpose8" => Undefined {
+            control: 83,
+            value: x7,
+        },
+        "PortamentoControl" => PortamentoControl(x7),
+        "HighResVelocity" => HighResVelocity(x7),
+        "Effects1Depth" => Effects1Depth(x7),
+        "Effects2Depth" => Effects2Depth(x7),
+        "Effects3Depth" => Effects3Depth(x7),
+        "Effects4Depth" => Effects4Depth(x7),
+        "Effects5Depth" => Effects5Depth(x7),
+        "ReverbSendLevel" => ReverbSendLevel(x7),
+        "TremoloDepth" => TremoloDepth(x7),
+        "ChorusSendLevel" => ChorusSendLevel(x7),
+        "CelesteDepth" => CelesteDepth(x7),
+        "PhaserDepth" => PhaserDepth(x7),
+        "DataIncrement" => DataIncrement(x7),
+        "DataDecrement" => DataDecrement(x7),
+        _ => return None,
+    };
+    Some(control)
+}
+
+/// Reverse of `label_channel_mode`
+pub fn channel_mode_from_label(label: &str, value: &MidiValue) -> Option<ChannelModeMsg> {
+    let msg = match label {
+        "AllSoundOff" => ChannelModeMsg::AllSoundOff,
+        "ResetAllControllers" => ChannelModeMsg::ResetAllControllers,
+        "LocalControl" => ChannelModeMsg::LocalControl(value_as_switch(value)),
+        "AllNotesOff" => ChannelModeMsg::AllNotesOff,
+        "OmniMode" => ChannelModeMsg::OmniMode(value_as_switch(value)),
+        "MonoMode" => ChannelModeMsg::PolyMode(PolyMode::Mono(value_as_u7(value))),
+        "PolyMode" => ChannelModeMsg::PolyMode(PolyMode::Poly),
+        _ => return None,
+    };
+    Some(msg)
+}
+
+/// Two numbered controllers sending the most and least significant 7 bits
+/// of one 14-bit value, configured per device
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HighResPair {
+    pub msb: u8,
+    /// Defaults to `msb` + 32, as for the standard controllers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lsb: Option<u8>,
+    /// The device always sends the LSB after the MSB, so only publish then
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wait_for_lsb: bool,
+}
+
+impl HighResPair {
+    pub fn lsb(&self) -> u8 {
+        self.lsb.unwrap_or(self.msb.saturating_add(32))
+    }
+}
+
+pub enum PairedControl {
+    /// Not one of the configured pairs; publish as usual
+    Unpaired,
+    /// An MSB, held until its LSB arrives
+    Waiting,
+    /// The MSB controller number and the 14-bit value to publish
+    Complete(u8, u16),
+}
+
+/// What has been received so far for one pair
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HighResState {
+    /// Latest MSB
+    pub msb: u8,
+    /// Whether LSBs have arrived as messages of their own; until then, an
+    /// MSB that midi-msg has already combined with an LSB of zero can't be
+    /// told apart from an MSB on its own
+    pub separate_lsb: bool,
+}
+
+/// Combine Control Changes on paired controllers into 14-bit values, using
+/// (and updating) the state of each pair, by MSB controller number. As in
+/// the MIDI spec, a new MSB resets the LSB to zero.
+pub fn pair_high_res(
+    pairs: &[HighResPair],
+    states: &mut HashMap<u8, HighResState>,
+    control: &ControlChange,
+) -> PairedControl {
+    // midi-msg reports CC 3, 9, 14, 15 and 20-31 as 14-bit, even on their own,
+    // along with the controller (number + 32) the LSB would have come from
+    let (number, value, lsb) = match *control {
+        ControlChange::Undefined { control, value } => (control, value, None),
+        ControlChange::UndefinedHighRes {
+            control1,
+            control2,
+            value,
+        } => (
+            control1,
+            (value >> 7) as u8,
+            Some((control2, (value & 0x7f) as u8)),
+        ),
+        _ => return PairedControl::Unpaired,
+    };
+    for pair in pairs {
+        if number == pair.msb {
+            let state = states.entry(pair.msb).or_default();
+            state.msb = value;
+            let lsb = match lsb {
+                Some((control, lsb)) if control == pair.lsb() => Some(lsb),
+                _ => None,
+            };
+            let lsb_included = match lsb {
+                Some(lsb) => lsb > 0 || !state.separate_lsb,
+                None => false,
+            };
+            return if lsb_included || !pair.wait_for_lsb {
+                PairedControl::Complete(pair.msb, combine(value, lsb.unwrap_or(0)))
+            } else {
+                PairedControl::Waiting
+            };
+        }
+        // An LSB on one of the controllers above only has its value in the
+        // top 7 bits
+        if number == pair.lsb() {
+            let state = states.entry(pair.msb).or_default();
+            state.separate_lsb = true;
+            return PairedControl::Complete(pair.msb, combine(state.msb, value));
+        }
+    }
+    PairedControl::Unpaired
+}
+
+fn combine(msb: u8, lsb: u8) -> u16 {
+    ((msb as u16) << 7) | (lsb as u16 & 0x7f)
+}
+
+pub fn high_res_numbered(control: u8, value: u16) -> ControlChange {
+    ControlChange::UndefinedHighRes {
+        control1: control,
+        control2: control + 32,
+        value,
+    }
+}
+
+fn value_as_u14(value: &MidiValue) -> u16 {
+    match *value {
+        MidiValue::LowRes(x) => (x as u16) << 7,
+        MidiValue::HighRes(x) => x,
+        MidiValue::Switch(on) => {
+            if on {
+                127 << 7
+            } else {
+                0
+            }
+        }
+    }
+}
+
+fn value_as_u7(value: &MidiValue) -> u8 {
+    match *value {
+        MidiValue::LowRes(x) => x,
+        MidiValue::HighRes(x) => (x >> 7) as u8,
+        MidiValue::Switch(on) => {
+            if on {
+                127
+            } else {
+                0
+            }
+        }
+    }
+}
+
+fn value_as_switch(value: &MidiValue) -> bool {
+    value_as_u7(value) >= 64
+}
+
+#[cfg(test)]
+mod tests {
+    use midi_msg::{ChannelModeMsg, ControlChange, PolyMode};
+
+    use super::*;
+    use crate::mediation::messages::{ControllerLabel, MidiValue};
+
+    fn special(label: &str) -> ControllerLabel {
+        ControllerLabel::Special(label.into())
+    }
+
+    #[test]
+    fn every_control_change_is_labelled() {
+        use ControlChange::*;
+        use MidiValue::{HighRes, LowRes, Switch};
+
+        let table: Vec<(ControlChange, ControllerLabel, MidiValue)> = vec![
+            (
+                Undefined {
+                    control: 102,
+                    value: 5,
+                },
+                ControllerLabel::Numbered(102),
+                LowRes(5),
+            ),
+            (
+                UndefinedHighRes {
+                    control1: 3,
+                    control2: 35,
+                    value: 1000,
+                },
+                special("UndefinedHighRes-3-35"),
+                HighRes(1000),
+            ),
+            (BankSelect(129), special("BankSelect"), HighRes(129)),
+            (ModWheel(1), special("ModWheel"), HighRes(1)),
+            (Breath(2), special("Breath"), HighRes(2)),
+            (Foot(3), special("Foot"), HighRes(3)),
+            (Portamento(4), special("Portamento"), HighRes(4)),
+            (Volume(5), special("Volume"), HighRes(5)),
+            (Balance(6), special("Balance"), HighRes(6)),
+            (Pan(7), special("Pan"), HighRes(7)),
+            (Expression(8), special("Expression"), HighRes(8)),
+            (Effect1(9), special("Effect1"), HighRes(9)),
+            (Effect2(10), special("Effect2"), HighRes(10)),
+            (GeneralPurpose1(11), special("GeneralPurpose1"), HighRes(11)),
+            (GeneralPurpose2(12), special("GeneralPurpose2"), HighRes(12)),
+            (GeneralPurpose3(13), special("GeneralPurpose3"), HighRes(13)),
+            (GeneralPurpose4(14), special("GeneralPurpose4"), HighRes(14)),
+            (DataEntry(15), special("DataEntry"), HighRes(15)),
+            (DataEntry2(1, 2), special("DataEntry"), HighRes(130)),
+            (Hold(127), special("Hold"), Switch(true)),
+            (Hold(63), special("Hold"), Switch(false)),
+            (
+                TogglePortamento(true),
+                special("TogglePortamento"),
+                Switch(true),
+            ),
+            (Sostenuto(64), special("Sostenuto"), Switch(true)),
+            (SoftPedal(0), special("SoftPedal"), Switch(false)),
+            (ToggleLegato(false), special("ToggleLegato"), Switch(false)),
+            (Hold2(100), special("Hold2"), Switch(true)),
+            (SoundControl1(1), special("SoundControl1"), LowRes(1)),
+            (SoundControl2(2), special("SoundControl2"), LowRes(2)),
+            (SoundControl3(3), special("SoundControl3"), LowRes(3)),
+            (SoundControl4(4), special("SoundControl4"), LowRes(4)),
+            (SoundControl5(5), special("SoundControl5"), LowRes(5)),
+            (SoundControl6(6), special("SoundControl6"), LowRes(6)),
+            (SoundControl7(7), special("SoundControl7"), LowRes(7)),
+            (SoundControl8(8), special("SoundControl8"), LowRes(8)),
+            (SoundControl9(9), special("SoundControl9"), LowRes(9)),
+            (SoundControl10(10), special("SoundControl10"), LowRes(10)),
+            (SoundVariation(11), special("SoundVariation"), LowRes(11)),
+            (Timbre(12), special("Timbre"), LowRes(12)),
+            (ReleaseTime(13), special("ReleaseTime"), LowRes(13)),
+            (AttackTime(14), special("AttackTime"), LowRes(14)),
+            (Brightness(15), special("Brightness"), LowRes(15)),
+            (DecayTime(16), special("DecayTime"), LowRes(16)),
+            (VibratoRate(17), special("VibratoRate"), LowRes(17)),
+            (VibratoDepth(18), special("VibratoDepth"), LowRes(18)),
+            (VibratoDelay(19), special("VibratoDelay"), LowRes(19)),
+            (GeneralPurpose5(20), special("GeneralPurpose5"), LowRes(20)),
+            (GeneralPurpose6(21), special("GeneralPurpose6"), LowRes(21)),
+            (GeneralPurpose7(22), special("GeneralPurpose7"), LowRes(22)),
+            (GeneralPurpose8(23), special("GeneralPurpose8"), LowRes(23)),
+            (
+                PortamentoControl(24),
+                special("PortamentoControl"),
+                LowRes(24),
+            ),
+            (HighResVelocity(25), special("HighResVelocity"), LowRes(25)),
+            (Effects1Depth(26), special("Effects1Depth"), LowRes(26)),
+            (Effects2Depth(27), special("Effects2Depth"), LowRes(27)),
+            (Effects3Depth(28), special("Effects3Depth"), LowRes(28)),
+            (Effects4Depth(29), special("Effects4Depth"), LowRes(29)),
+            (Effects5Depth(30), special("Effects5Depth"), LowRes(30)),
+            (ReverbSendLevel(31), special("ReverbSendLevel"), LowRes(31)),
+            (TremoloDepth(32), special("TremoloDepth"), LowRes(32)),
+            (ChorusSendLevel(33), special("ChorusSendLevel"), LowRes(33)),
+            (CelesteDepth(34), special("CelesteDepth"), LowRes(34)),
+            (PhaserDepth(35), special("PhaserDepth"), LowRes(35)),
+            (DataIncrement(1), special("DataIncrement"), LowRes(1)),
+            (DataDecrement(2), special("DataDecrement"), LowRes(2)),
+        ];
+
+        for (control, expected_label, expected_value) in table {
+            assert_eq!(
+                label_control_change(&control),
+                Some((expected_label, expected_value)),
+                "{:?}",
+                control
+            );
+        }
+    }
+
+    #[test]
+    fn every_channel_mode_is_labelled() {
+        let table = vec![
+            (
+                ChannelModeMsg::AllSoundOff,
+                special("AllSoundOff"),
+                MidiValue::Switch(true),
+            ),
+            (
+                ChannelModeMsg::ResetAllControllers,
+                special("ResetAllControllers"),
+                MidiValue::Switch(true),
+            ),
+            (
+                ChannelModeMsg::LocalControl(false),
+                special("LocalControl"),
+                MidiValue::Switch(false),
+            ),
+            (
+                ChannelModeMsg::AllNotesOff,
+                special("AllNotesOff"),
+                MidiValue::Switch(true),
+            ),
+            (
+                ChannelModeMsg::OmniMode(true),
+                special("OmniMode"),
+                MidiValue::Switch(true),
+            ),
+            (
+                ChannelModeMsg::PolyMode(PolyMode::Mono(4)),
+                special("MonoMode"),
+                MidiValue::LowRes(4),
+            ),
+            (
+                ChannelModeMsg::PolyMode(PolyMode::Poly),
+                special("PolyMode"),
+                MidiValue::Switch(true),
+            ),
+        ];
+
+        for (msg, expected_label, expected_value) in table {
+            assert_eq!(
+                label_channel_mode(&msg),
+                (expected_label.clone(), expected_value.clone()),
+                "{:?}",
+                msg
+            );
+            if let ControllerLabel::Special(label) = expected_label {
+                assert_eq!(channel_mode_from_label(&label, &expected_value), Some(msg));
+            }
+        }
+    }
+
+    #[test]
+    fn special_labels_round_trip() {
+        let controls = vec![
+            ControlChange::BankSelect(300),
+            ControlChange::Volume(16000),
+            ControlChange::Hold(127),
+            ControlChange::ToggleLegato(true),
+            ControlChange::SoundControl5(42),
+            ControlChange::PhaserDepth(1),
+            ControlChange::UndefinedHighRes {
+                control1: 9,
+                control2: 41,
+                value: 777,
+            },
+        ];
+        for control in controls {
+            let (label, value) = label_control_change(&control).unwrap();
+            let ControllerLabel::Special(label) = label else {
+                panic!("expected a special label for {:?}", control);
+            };
+            assert_eq!(control_change_from_label(&label, &value), Some(control));
+        }
+    }
+
+    #[test]
+    fn special_labels_send_the_right_controllers() {
+        // 1000 is 7 << 7 | 104
+        let high_res = |control: u8| vec![control, 7, control + 32, 104];
+        let low_res = |control: u8| vec![control, 42];
+        let switch = |control: u8| vec![control, 127];
+
+        let table: Vec<(&str, Vec<u8>)> = vec![
+            ("UndefinedHighRes-3-35", high_res(3)),
+            ("BankSelect", high_res(0)),
+            ("ModWheel", high_res(1)),
+            ("Breath", high_res(2)),
+            ("Foot", high_res(4)),
+            ("Portamento", high_res(5)),
+            ("DataEntry", high_res(6)),
+            ("Volume", high_res(7)),
+            ("Balance", high_res(8)),
+            ("Pan", high_res(10)),
+            ("Expression", high_res(11)),
+            ("Effect1", high_res(12)),
+            ("Effect2", high_res(13)),
+            ("GeneralPurpose1", high_res(16)),
+            ("GeneralPurpose2", high_res(17)),
+            ("GeneralPurpose3", high_res(18)),
+            ("GeneralPurpose4", high_res(19)),
+            ("Hold", switch(64)),
+            ("TogglePortamento", switch(65)),
+            ("Sostenuto", switch(66)),
+            ("SoftPedal", switch(67)),
+            ("ToggleLegato", switch(68)),
+            ("Hold2", switch(69)),
+            ("SoundControl1", low_res(70)),
+            ("SoundControl2", low_res(71)),
+            ("SoundControl3", low_res(72)),
+            ("SoundControl4", low_res(73)),
+            ("SoundControl5", low_res(74)),
+            ("SoundControl6", low_res(75)),
+            ("SoundControl7", low_res(76)),
+            ("SoundControl8", low_res(77)),
+            ("SoundControl9", low_res(78)),
+            ("SoundControl10", low_res(79)),
+            ("SoundVariation", low_res(70)),
+            ("Timbre", low_res(71)),
+            ("ReleaseTime", low_res(72)),
+            ("AttackTime", low_res(73)),
+            ("Brightness", low_res(74)),
+            ("DecayTime", low_res(75)),
+            ("VibratoRate", low_res(76)),
+            ("VibratoDepth", low_res(77)),
+            ("VibratoDelay", low_res(78)),
+            ("GeneralPurpose5", low_res(80)),
+            ("GeneralPurpose6", low_res(81)),
+            ("GeneralPurpose7", low_res(82)),
+            ("GeneralPurpose8", low_res(83)),
+            ("PortamentoControl", low_res(84)),
+            ("HighResVelocity", low_res(88)),
+            ("Effects1Depth", low_res(91)),
+            ("Effects2Depth", low_res(92)),
+            ("Effects3Depth", low_res(93)),
+            ("Effects4Depth", low_res(94)),
+            ("Effects5Depth", low_res(95)),
+            ("ReverbSendLevel", low_res(91)),
+            ("TremoloDepth", low_res(92)),
+            ("ChorusSendLevel", low_res(93)),
+            ("CelesteDepth", low_res(94)),
+            ("PhaserDepth", low_res(95)),
+            ("DataIncrement", low_res(96)),
+            ("DataDecrement", low_res(97)),
+        ];
+
+        for (label, expected) in table {
+            let value = match expected.len() {
+                4 => MidiValue::HighRes(1000),
+                _ if expected[1] == 127 => MidiValue::Switch(true),
+                _ => MidiValue::LowRes(42),
+            };
+            let control = control_change_from_label(label, &value).unwrap();
+            assert_eq!(control.to_midi_running(), expected, "{}", label);
+        }
+    }
+
+    #[test]
+    fn paired_controllers_combine_into_14_bits() {
+        let pairs = vec![
+            HighResPair {
+                msb: 20,
+                lsb: None,
+                wait_for_lsb: false,
+            },
+            HighResPair {
+                msb: 102,
+                lsb: Some(103),
+                wait_for_lsb: true,
+            },
+        ];
+        let mut states = HashMap::new();
+        let mut pair = |control| match pair_high_res(&pairs, &mut states, &control) {
+            PairedControl::Unpaired => None,
+            PairedControl::Waiting => Some((0, 0)),
+            PairedControl::Complete(msb, value) => Some((msb, value)),
+        };
+
+        assert_eq!(
+            pair(ControlChange::UndefinedHighRes {
+                control1: 20,
+                control2: 52,
+                value: 10 << 7,
+            }),
+            Some((20, 1280))
+        );
+        assert_eq!(
+            pair(ControlChange::Undefined {
+                control: 52,
+                value: 5,
+            }),
+            Some((20, 1285))
+        );
+        assert_eq!(
+            pair(ControlChange::Undefined {
+                control: 102,
+                value: 1,
+            }),
+            Some((0, 0))
+        );
+        assert_eq!(
+            pair(ControlChange::Undefined {
+                control: 103,
+                value: 2,
+            }),
+            Some((102, 130))
+        );
+
+        // Until LSBs are seen on their own, an MSB may already include an LSB of zero
+        let pairs = vec![HighResPair {
+            msb: 20,
+            lsb: None,
+            wait_for_lsb: true,
+        }];
+        let mut states = HashMap::new();
+        let mut pair = |control| match pair_high_res(&pairs, &mut states, &control) {
+            PairedControl::Unpaired => None,
+            PairedControl::Waiting => Some((0, 0)),
+            PairedControl::Complete(msb, value) => Some((msb, value)),
+        };
+        let msb = |value: u16| ControlChange::UndefinedHighRes {
+            control1: 20,
+            control2: 52,
+            value: value << 7,
+        };
+        assert_eq!(pair(msb(10)), Some((20, 1280)));
+        assert_eq!(
+            pair(ControlChange::Undefined {
+                control: 52,
+                value: 0,
+            }),
+            Some((20, 1280))
+        );
+        assert_eq!(pair(msb(11)), Some((0, 0)));
+        assert_eq!(
+            pair(ControlChange::Undefined {
+                control: 52,
+                value: 0,
+            }),
+            Some((20, 1408))
+        );
+        assert_eq!(
+            pair(ControlChange::Undefined {
+                control: 104,
+                value: 2,
+            }),
+            None
+        );
+
+        // midi-msg reports CC 14 and 15 as 14-bit controllers, each with its
+        // own (missing) LSB on CC 46 and 47
+        let pairs = vec![HighResPair {
+            msb: 14,
+            lsb: Some(15),
+            wait_for_lsb: true,
+        }];
+        let mut states = HashMap::new();
+        let mut pair = |control| match pair_high_res(&pairs, &mut states, &control) {
+            PairedControl::Unpaired => None,
+            PairedControl::Waiting => Some((0, 0)),
+            PairedControl::Complete(msb, value) => Some((msb, value)),
+        };
+        let lone = |control: u8, value: u16| ControlChange::UndefinedHighRes {
+            control1: control,
+            control2: control + 32,
+            value: value << 7,
+        };
+        assert_eq!(pair(lone(14, 10)), Some((0, 0)));
+        assert_eq!(pair(lone(15, 5)), Some((14, 1285)));
+    }
+
+    #[test]
+    fn parameters_are_not_labelled() {
+        assert_eq!(
+            label_control_change(&ControlChange::Parameter(midi_msg::Parameter::Null)),
+            None
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use super::{mappings::KnobMapping, messages::MidiValue, MAX_HIRES, MAX_LORES};
+
+pub const DEFAULT_STEPS: u8 = 4;
+
+/// Response curve applied to a knob's normalised position
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Rises quickly at first, then levels off
+    Logarithmic,
+    /// Rises slowly at first, then quickly; good for volume
+    Exponential,
+    /// Slow at both ends, quick in the middle
+    Sigmoid,
+    /// Snaps to this many evenly spaced values, including both ends
+    Stepped(u8),
+}
+
+impl Curve {
+    /// All curves, e.g. for the GUI
+    pub const ALL: [Curve; 5] = [
+        Curve::Linear,
+        Curve::Logarithmic,
+        Curve::Exponential,
+        Curve::Sigmoid,
+        Curve::Stepped(DEFAULT_STEPS),
+    ];
+
+    /// Name without any parameters, e.g. "Stepped"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Linear => "Linear",
+            Curve::Logarithmic => "Logarithmic",
+            Curve::Exponential => "Exponential",
+            Curve::Sigmoid => "Sigmoid",
+            Curve::Stepped(_) => "Stepped",
+        }
+    }
+
+    /// Map 0.0-1.0 onto 0.0-1.0
+    pub fn apply(&self, x: f32) -> f32 {
+        match self {
+            Curve::Linear => x,
+            Curve::Logarithmic => (1.0 + 9.0 * x).log10(),
+            Curve::Exponential => (10f32.powf(x) - 1.0) / 9.0,
+            Curve::Sigmoid => x * x * (3.0 - 2.0 * x),
+            Curve::Stepped(steps) => {
+                if *steps < 2 {
+                    0.0
+                } else {
+                    let intervals = (*steps - 1) as f32;
+                    (x * intervals).round() / intervals
+                }
+            }
+        }
+    }
+}
+
+/// 0.0-1.0, whatever the resolution
+pub fn normalise(value: &MidiValue) -> f32 {
+    match value {
+        MidiValue::LowRes(x) => *x as f32 / MAX_LORES as f32,
+        MidiValue::HighRes(x) => (*x as f32 / MAX_HIRES as f32).min(1.0),
+        MidiValue::Switch(on) => {
+            if *on {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+impl KnobMapping {
+    /// The value published for this knob: the position, inverted if
+    /// required, with the dead zones removed and the curve applied, scaled
+    /// onto the output range
+    pub fn output(&self, value: &MidiValue) -> f32 {
+        self.output_at(normalise(value))
+    }
+
+    /// As `output`, for a normalised position
+    pub fn output_at(&self, position: f32) -> f32 {
+        let mut x = position;
+        if self.invert {
+            x = 1.0 - x;
+        }
+        let low = self.dead_zone_low.unwrap_or(0.0).clamp(0.0, 1.0);
+        let high = self.dead_zone_high.unwrap_or(0.0).clamp(0.0, 1.0 - low);
+        let live = 1.0 - low - high;
+        x = if live > 0.0 {
+            ((x - low) / live).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (min, max) = self.range();
+        min + self.curve.unwrap_or_default().apply(x) * (max - min)
+    }
+
+    /// Output range, 0.0-1.0 by default
+    pub fn range(&self) -> (f32, f32) {
+        (self.min.unwrap_or(0.0), self.max.unwrap_or(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mediation::messages::ControllerLabel;
+
+    #[test]
+    fn knob_output() {
+        let plain = KnobMapping::new(None, ControllerLabel::Numbered(1));
+        let knob = |f: fn(&mut KnobMapping)| {
+            let mut knob = plain.clone();
+            f(&mut knob);
+            knob
+        };
+        let table = [
+            (plain.clone(), 0.5, 0.5),
+            (knob(|k| k.invert = true), 0.25, 0.75),
+            (knob(|k| k.dead_zone_low = Some(0.1)), 0.05, 0.0),
+            (knob(|k| k.dead_zone_low = Some(0.1)), 0.55, 0.5),
+            (knob(|k| k.dead_zone_high = Some(0.2)), 0.9, 1.0),
+            (knob(|k| k.dead_zone_high = Some(0.2)), 0.4, 0.5),
+            // Dead zones covering all the travel
+            (
+                knob(|k| {
+                    k.dead_zone_low = Some(0.6);
+                    k.dead_zone_high = Some(0.6);
+                }),
+                0.9,
+                0.0,
+            ),
+            // Dead zones apply after inversion
+            (
+                knob(|k| {
+                    k.invert = true;
+                    k.dead_zone_low = Some(0.1);
+                }),
+                0.95,
+                0.0,
+            ),
+            (
+                knob(|k| {
+                    k.min = Some(10.0);
+                    k.max = Some(20.0);
+                }),
+                0.5,
+                15.0,
+            ),
+            (
+                knob(|k| {
+                    k.min = Some(1.0);
+                    k.max = Some(0.0);
+                }),
+                0.25,
+                0.75,
+            ),
+            (knob(|k| k.curve = Some(Curve::Stepped(3))), 0.2, 0.0),
+            (knob(|k| k.curve = Some(Curve::Stepped(3))), 0.3, 0.5),
+            (knob(|k| k.curve = Some(Curve::Stepped(3))), 0.8, 1.0),
+            (knob(|k| k.curve = Some(Curve::Stepped(1))), 0.8, 0.0),
+            (
+                knob(|k| {
+                    k.curve = Some(Curve::Stepped(5));
+                    k.max = Some(100.0);
+                }),
+                0.4,
+                50.0,
+            ),
+        ];
+        for (knob, position, expected) in table {
+            let output = knob.output_at(position);
+            assert!(
+                (output - expected).abs() < 1e-5,
+                "{:?} at {}: {}",
+                knob,
+                position,
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn curves_keep_the_ends() {
+        for curve in Curve::ALL {
+            assert!(curve.apply(0.0).abs() < 1e-6, "{:?}", curve);
+            assert!((curve.apply(1.0) - 1.0).abs() < 1e-6, "{:?}", curve);
+        }
+        assert!(Curve::Logarithmic.apply(0.5) > 0.5);
+        assert!(Curve::Exponential.apply(0.5) < 0.5);
+        assert_eq!(normalise(&MidiValue::LowRes(MAX_LORES)), 1.0);
+        assert_eq!(normalise(&MidiValue::HighRes(MAX_HIRES)), 1.0);
+        assert_eq!(normalise(&MidiValue::Switch(false)), 0.0);
+    }
+}
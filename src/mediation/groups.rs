@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+/// Controls on one device that are published together, so that consumers
+/// don't have to reassemble them from separate messages. Members are given
+/// by their position in the device's "knobs" and "buttons" lists.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlGroup {
+    pub name: String,
+    pub kind: GroupKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GroupKind {
+    /// Two knobs, published as one {x, y} message
+    XyPad { x: usize, y: usize },
+    /// Several knobs, published as a list of positions
+    FaderBank { knobs: Vec<usize> },
+    /// An (endless) encoder knob and its push button
+    EncoderWithPush { knob: usize, button: usize },
+}
+
+impl ControlGroup {
+    pub fn has_knob(&self, index: usize) -> bool {
+        match &self.kind {
+            GroupKind::XyPad { x, y } => *x == index || *y == index,
+            GroupKind::FaderBank { knobs } => knobs.contains(&index),
+            GroupKind::EncoderWithPush { knob, .. } => *knob == index,
+        }
+    }
+
+    /// Follow two knobs that swapped places in the device's list
+    pub fn swap_knobs(&mut self, a: usize, b: usize) {
+        self.remap_knobs(|k| {
+            if k == a {
+                b
+            } else if k == b {
+                a
+            } else {
+                k
+            }
+        });
+    }
+
+    /// Follow the knobs after this one when it is removed from the list;
+    /// the group itself must not include it
+    pub fn remove_knob(&mut self, index: usize) {
+        self.remap_knobs(|k| if k > index { k - 1 } else { k });
+    }
+
+    /// Follow the knobs to their new indexes after the list is edited
+    fn remap_knobs(&mut self, new_index: impl Fn(usize) -> usize) {
+        match &mut self.kind {
+            GroupKind::XyPad { x, y } => {
+                *x = new_index(*x);
+                *y = new_index(*y);
+            }
+            GroupKind::FaderBank { knobs } => {
+                for knob in knobs.iter_mut() {
+                    *knob = new_index(*knob);
+                }
+            }
+            GroupKind::EncoderWithPush { knob, .. } => *knob = new_index(*knob),
+        }
+    }
+
+    pub fn has_button(&self, index: usize) -> bool {
+        match &self.kind {
+            GroupKind::EncoderWithPush { button, .. } => *button == index,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(kind: GroupKind) -> ControlGroup {
+        ControlGroup {
+            name: "group".into(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn groups_follow_reordered_knobs() {
+        let mut pad = group(GroupKind::XyPad { x: 1, y: 2 });
+        let mut bank = group(GroupKind::FaderBank {
+            knobs: vec![0, 2, 3],
+        });
+        let mut encoder = group(GroupKind::EncoderWithPush { knob: 3, button: 0 });
+        for group in [&mut pad, &mut bank, &mut encoder] {
+            // Knob 2 moved up, then knob 3 moved down
+            group.swap_knobs(1, 2);
+            group.swap_knobs(3, 4);
+        }
+        assert_eq!(pad.kind, GroupKind::XyPad { x: 2, y: 1 });
+        assert_eq!(
+            bank.kind,
+            GroupKind::FaderBank {
+                knobs: vec![0, 1, 4]
+            }
+        );
+        assert_eq!(
+            encoder.kind,
+            GroupKind::EncoderWithPush { knob: 4, button: 0 }
+        );
+        assert!(encoder.has_knob(4) && !encoder.has_knob(3));
+    }
+
+    #[test]
+    fn groups_follow_knobs_after_a_deleted_one() {
+        let mut pad = group(GroupKind::XyPad { x: 0, y: 3 });
+        let mut bank = group(GroupKind::FaderBank {
+            knobs: vec![4, 5, 6],
+        });
+        let mut encoder = group(GroupKind::EncoderWithPush { knob: 2, button: 2 });
+        for group in [&mut pad, &mut bank, &mut encoder] {
+            assert!(!group.has_knob(1));
+            group.remove_knob(1);
+        }
+        assert_eq!(pad.kind, GroupKind::XyPad { x: 0, y: 2 });
+        assert_eq!(
+            bank.kind,
+            GroupKind::FaderBank {
+                knobs: vec![3, 4, 5]
+            }
+        );
+        // Buttons are in a list of their own, so stay put
+        assert_eq!(
+            encoder.kind,
+            GroupKind::EncoderWithPush { knob: 1, button: 2 }
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeSet;
+
+/// Notes currently sounding on a single channel, taking the sustain pedal
+/// into account. Every update returns whether the set of sounding notes
+/// changed.
+#[derive(Default, Debug)]
+pub struct HeldNotes {
+    /// Keys physically held down
+    pressed: BTreeSet<u8>,
+    /// Keys released while the sustain pedal was down
+    sustained: BTreeSet<u8>,
+    sustain: bool,
+}
+
+impl HeldNotes {
+    pub fn note_on(&mut self, note: u8) -> bool {
+        let was_held = self.is_held(note);
+        self.pressed.insert(note);
+        !was_held
+    }
+
+    pub fn note_off(&mut self, note: u8) -> bool {
+        if !self.pressed.remove(&note) {
+            return false;
+        }
+        if self.sustain {
+            self.sustained.insert(note);
+            false
+        } else {
+            true
+        }
+    }
+
+    pub fn set_sustain(&mut self, sustain: bool) -> bool {
+        self.sustain = sustain;
+        if sustain {
+            return false;
+        }
+        let released: Vec<u8> = self.sustained.difference(&self.pressed).copied().collect();
+        self.sustained.clear();
+        !released.is_empty()
+    }
+
+    /// For "All Notes Off" and "All Sound Off"; the pedal state is kept
+    pub fn clear(&mut self) -> bool {
+        let changed = !self.pressed.is_empty() || !self.sustained.is_empty();
+        self.pressed.clear();
+        self.sustained.clear();
+        changed
+    }
+
+    pub fn is_held(&self, note: u8) -> bool {
+        self.pressed.contains(&note) || self.sustained.contains(&note)
+    }
+
+    /// Sorted, lowest note first
+    pub fn notes(&self) -> Vec<u8> {
+        self.pressed.union(&self.sustained).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_released_while_sustained_keep_sounding() {
+        let mut held = HeldNotes::default();
+        assert!(held.note_on(60));
+        assert!(held.note_on(64));
+        assert!(!held.set_sustain(true));
+        assert!(!held.note_off(60));
+        assert_eq!(held.notes(), vec![60, 64]);
+        assert!(!held.note_off(64));
+        assert!(held.set_sustain(false));
+        assert_eq!(held.notes(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn restruck_sustained_note_outlasts_the_pedal() {
+        let mut held = HeldNotes::default();
+        held.set_sustain(true);
+        held.note_on(60);
+        held.note_off(60);
+        // Already sounding, so nothing changes
+        assert!(!held.note_on(60));
+        assert!(!held.set_sustain(false));
+        assert_eq!(held.notes(), vec![60]);
+        assert!(held.note_off(60));
+        assert!(!held.is_held(60));
+    }
+
+    #[test]
+    fn pedal_up_releases_only_sustained_notes() {
+        let mut held = HeldNotes::default();
+        held.note_on(48);
+        held.set_sustain(true);
+        held.note_on(60);
+        held.note_off(60);
+        assert!(held.set_sustain(false));
+        assert_eq!(held.notes(), vec![48]);
+        // With nothing sustained, the pedal changes nothing
+        assert!(!held.set_sustain(true));
+        assert!(!held.set_sustain(false));
+        assert!(!held.note_off(72));
+    }
+
+    #[test]
+    fn clear_keeps_the_pedal_down() {
+        let mut held = HeldNotes::default();
+        held.set_sustain(true);
+        held.note_on(60);
+        held.note_off(60);
+        assert!(held.clear());
+        assert!(!held.clear());
+        held.note_on(62);
+        assert!(!held.note_off(62));
+        assert_eq!(held.notes(), vec![62]);
+    }
+}
@@ -1,36 +1,458 @@
-use log::{debug, info};
-use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
 
-use super::messages::ControllerLabel;
-use anyhow::anyhow;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Clone, Debug)]
+use super::{
+    buttons::ButtonMapping, controllers::HighResPair, curves::Curve, groups::ControlGroup,
+    messages::ControllerLabel, relative::RelativeEncoding, smoothing::Smoothing,
+    ControllerValueMode,
+};
+use anyhow::{anyhow, Context};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct KnobMapping {
+    /// Optional label, e.g. "Filter cutoff"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Only match this MIDI channel (1-16)
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub channel: Option<u8>,
+    /// Only match ports whose name contains this text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
     pub controller: ControllerLabel,
+    /// Output value at the bottom of the knob's travel (default 0.0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    /// Output value at the top of the knob's travel (default 1.0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub curve: Option<Curve>,
+    /// Reverse the direction of travel
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub invert: bool,
+    /// Fraction of travel at the bottom (after any inversion) that gives
+    /// the minimum output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_zone_low: Option<f32>,
+    /// Fraction of travel at the top that gives the maximum output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_zone_high: Option<f32>,
+    /// Soft takeover: after the stored value is changed (see "setKnobs"),
+    /// ignore the physical knob until it reaches the stored value
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pickup: bool,
+    /// Smooth the published position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smoothing: Option<Smoothing>,
+    /// Publish this knob (and its Control Changes) at most once in this many
+    /// milliseconds, with the latest value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_ms: Option<u64>,
+    /// Override the device's value mode for this controller
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ControllerValueMode>,
+    /// Treat this controller as a relative encoder, overriding the device
+    /// and global settings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative: Option<RelativeEncoding>,
+    /// The "down" button, for controllers using `IncDecButtons`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decrement: Option<ControllerLabel>,
+}
+
+impl KnobMapping {
+    pub fn new(channel: Option<u8>, controller: ControllerLabel) -> Self {
+        KnobMapping {
+            name: None,
+            channel,
+            port: None,
+            controller,
+            min: None,
+            max: None,
+            curve: None,
+            invert: false,
+            dead_zone_low: None,
+            dead_zone_high: None,
+            pickup: false,
+            smoothing: None,
+            rate_limit_ms: None,
+            mode: None,
+            relative: None,
+            decrement: None,
+        }
+    }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+/// How a Control Change matched a knob
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KnobMatch {
+    Controller,
+    /// The knob's `decrement` button
+    Decrement,
+}
+
+impl KnobMapping {
+    /// Whether this knob is published on a timer (see `smoothing`), rather
+    /// than straight away
+    pub fn is_timed(&self) -> bool {
+        self.smoothing.is_some() || self.rate_limit_ms.is_some()
+    }
+
+    /// Whether a Control Change from this port and channel is for this knob;
+    /// the controller must match, and so must every filter that is given
+    pub fn matches(
+        &self,
+        port_name: &str,
+        channel: u8,
+        controller: &ControllerLabel,
+    ) -> Option<KnobMatch> {
+        if !filters_match(self.channel, self.port.as_deref(), port_name, channel) {
+            return None;
+        }
+        if self.controller == *controller {
+            Some(KnobMatch::Controller)
+        } else if self.decrement.as_ref() == Some(controller) {
+            Some(KnobMatch::Decrement)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the optional channel and port-name filters of a mapping allow a
+/// message from this port and channel
+pub fn filters_match(
+    channel_filter: Option<u8>,
+    port_filter: Option<&str>,
+    port_name: &str,
+    channel: u8,
+) -> bool {
+    channel_filter.is_none_or(|c| c == channel) && port_filter.is_none_or(|p| port_name.contains(p))
+}
+
+/// The first knob matching this Control Change, with its position in the list
+pub fn find_knob<'a>(
+    knobs: &'a [KnobMapping],
+    port_name: &str,
+    channel: u8,
+    controller: &ControllerLabel,
+) -> Option<(usize, &'a KnobMapping, KnobMatch)> {
+    knobs.iter().enumerate().find_map(|(i, knob)| {
+        knob.matches(port_name, channel, controller)
+            .map(|knob_match| (i, knob, knob_match))
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct DeviceWithMapping {
-    name: String,
-    knobs: Vec<KnobMapping>,
+    pub name: String,
+    #[serde(default)]
+    pub knobs: Vec<KnobMapping>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<ButtonMapping>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ControlGroup>,
+    /// Treat every controller on this device as a relative encoder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative: Option<RelativeEncoding>,
+    /// Value mode for this device, overriding the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ControllerValueMode>,
+    /// Added to the knob indexes for this device, so that several devices
+    /// can make up one continuous bank of knobs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_offset: Option<u8>,
+    /// Numbered controllers to combine into 14-bit values
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub high_res: Vec<HighResPair>,
+}
+
+const MAPPINGS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Mapping files found in this directory (under the user's config directory)
+/// are loaded automatically
+const CONFIG_DIR_NAME: &str = "tether-midi-mediator";
+
+/// e.g. ~/.config/tether-midi-mediator/mappings on Linux
+pub fn user_mappings_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME).join("mappings"))
+}
+
+/// The built-in mappings, merged with (and overridden by) any mapping files
+/// in the user config directory and then the given path, which may be a file
+/// or a directory. Devices are matched by name; a later definition replaces
+/// an earlier one. Files that cannot be loaded are skipped, with an error
+/// logged.
+pub fn load_all_mappings(mappings_path: Option<&Path>) -> Vec<DeviceWithMapping> {
+    let mut devices = match parse_mappings(include_str!("../../mappings/knobs.json")) {
+        Ok(devices) => devices,
+        Err(e) => {
+            error!("Failed to load built-in knob mappings: {}", e);
+            Vec::new()
+        }
+    };
+    debug!("...Loaded {} built-in knob mappings OK", devices.len());
+
+    let (files, errors) = mapping_files_to_load(mappings_path);
+    for e in errors {
+        error!("{}", e);
+    }
+    for file in files {
+        match load_mapping_file(&file) {
+            Ok(loaded) => {
+                info!(
+                    "Loaded {} knob mapping(s) from {}",
+                    loaded.len(),
+                    file.display()
+                );
+                merge_mappings(&mut devices, loaded);
+            }
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    devices
+}
+
+/// Mapping files from the user config directory and then the given path, in
+/// the order they should be merged, plus any errors finding them
+fn mapping_files_to_load(mappings_path: Option<&Path>) -> (Vec<PathBuf>, Vec<anyhow::Error>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    let user_dir = user_mappings_dir().filter(|dir| dir.is_dir());
+    for path in user_dir
+        .iter()
+        .map(|dir| dir.as_path())
+        .chain(mappings_path)
+    {
+        match mapping_files(path) {
+            Ok(found) => files.extend(found),
+            Err(e) => errors.push(e),
+        }
+    }
+    (files, errors)
 }
 
-pub fn load_knob_mappings(name: &str) -> anyhow::Result<Vec<KnobMapping>> {
-    let json_str = include_str!("../../mappings/knobs.json");
-    let all_mappings = serde_json::from_str::<Vec<DeviceWithMapping>>(json_str)
-        .expect("failed to load knob mappings");
-    debug!("...Loaded {} knob mappings OK", all_mappings.len());
-    match all_mappings.iter().find(|x| x.name == name) {
+/// Poll the mapping files for changes, and send the complete, merged
+/// mappings whenever a file is added, removed or modified
+pub fn watch_mappings(
+    mappings_path: Option<PathBuf>,
+    tx: Sender<Vec<DeviceWithMapping>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let fingerprint = || {
+            mapping_files_to_load(mappings_path.as_deref())
+                .0
+                .into_iter()
+                .map(|file| {
+                    let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+                    (file, modified)
+                })
+                .collect::<Vec<(PathBuf, Option<SystemTime>)>>()
+        };
+        let mut last_fingerprint = fingerprint();
+        loop {
+            std::thread::sleep(MAPPINGS_POLL_INTERVAL);
+            let current = fingerprint();
+            if current != last_fingerprint {
+                info!("Knob mapping files changed; reloading...");
+                last_fingerprint = current;
+                if tx
+                    .send(load_all_mappings(mappings_path.as_deref()))
+                    .is_err()
+                {
+                    // Nobody listening any more
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// The file itself, or every .json file in the directory (in name order)
+pub fn mapping_files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let entries = fs::read_dir(path)
+        .with_context(|| format!("Cannot read knob mappings from {}", path.display()))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+pub fn load_mapping_file(path: &Path) -> anyhow::Result<Vec<DeviceWithMapping>> {
+    let json_str = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read knob mapping file {}", path.display()))?;
+    parse_mappings(&json_str)
+        .with_context(|| format!("Invalid knob mapping file {}", path.display()))
+}
+
+/// A mapping file contains either a list of devices, or just one
+fn parse_mappings(json_str: &str) -> anyhow::Result<Vec<DeviceWithMapping>> {
+    if json_str.trim_start().starts_with('{') {
+        Ok(vec![serde_json::from_str::<DeviceWithMapping>(json_str)?])
+    } else {
+        Ok(serde_json::from_str::<Vec<DeviceWithMapping>>(json_str)?)
+    }
+}
+
+/// Save the mapping for one device, either as its own file (named after the
+/// device) if the target is a directory, or merged into the target file
+/// (created if it is a new .json path); returns the path written
+pub fn save_device_mapping(device: &DeviceWithMapping, target: &Path) -> anyhow::Result<PathBuf> {
+    // A path that doesn't exist yet is a file if it has a .json extension
+    let is_file = target.is_file()
+        || (!target.exists() && target.extension().is_some_and(|ext| ext == "json"));
+    let (path, devices) = if target.is_file() {
+        let mut devices = load_mapping_file(target)?;
+        merge_mappings(&mut devices, vec![device.clone()]);
+        (target.to_path_buf(), devices)
+    } else if is_file {
+        if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Cannot create directory {}", parent.display()))?;
+        }
+        (target.to_path_buf(), vec![device.clone()])
+    } else {
+        fs::create_dir_all(target)
+            .with_context(|| format!("Cannot create directory {}", target.display()))?;
+        let file_name: String = device
+            .name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        (
+            target.join(format!("{file_name}.json")),
+            vec![device.clone()],
+        )
+    };
+    let json_str = serde_json::to_string_pretty(&devices)?;
+    fs::write(&path, json_str).with_context(|| format!("Cannot write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Add the new devices, replacing any existing device with the same name
+pub fn merge_mappings(devices: &mut Vec<DeviceWithMapping>, new_devices: Vec<DeviceWithMapping>) {
+    for device in new_devices {
+        match devices.iter_mut().find(|d| d.name == device.name) {
+            Some(existing) => {
+                debug!("Mapping for device \"{}\" overridden", device.name);
+                *existing = device;
+            }
+            None => devices.push(device),
+        }
+    }
+}
+
+pub fn find_knob_mapping(
+    devices: &[DeviceWithMapping],
+    name: &str,
+) -> anyhow::Result<DeviceWithMapping> {
+    match devices.iter().find(|x| x.name == name) {
         Some(device) => {
-            let knobs = device.clone().knobs;
             info!(
                 "Loaded {} knob mappings for device \"{}\" OK",
+                device.knobs.len(),
                 &device.name,
-                knobs.len()
             );
-            Ok(knobs)
+            Ok(device.clone())
         }
         None => Err(anyhow!("Could not find device with name  {}", name)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PORT: &str = "Faderfox PC4 0";
+
+    fn numbered(n: u8) -> ControllerLabel {
+        ControllerLabel::Numbered(n)
+    }
+
+    #[test]
+    fn controller_must_match() {
+        let knob = KnobMapping::new(None, numbered(20));
+        assert_eq!(
+            knob.matches(PORT, 1, &numbered(20)),
+            Some(KnobMatch::Controller)
+        );
+        assert_eq!(
+            knob.matches(PORT, 16, &numbered(20)),
+            Some(KnobMatch::Controller)
+        );
+        assert_eq!(knob.matches(PORT, 1, &numbered(21)), None);
+        assert_eq!(
+            knob.matches(PORT, 1, &ControllerLabel::Special("Volume".into())),
+            None
+        );
+    }
+
+    #[test]
+    fn channel_and_controller_must_both_match() {
+        let knob = KnobMapping::new(Some(2), numbered(20));
+        assert_eq!(
+            knob.matches(PORT, 2, &numbered(20)),
+            Some(KnobMatch::Controller)
+        );
+        assert_eq!(knob.matches(PORT, 1, &numbered(20)), None);
+        // Matching the channel alone is not enough
+        assert_eq!(knob.matches(PORT, 2, &numbered(21)), None);
+    }
+
+    #[test]
+    fn port_filter_must_match() {
+        let mut knob = KnobMapping::new(None, numbered(20));
+        knob.port = Some("PC4".into());
+        assert_eq!(
+            knob.matches(PORT, 1, &numbered(20)),
+            Some(KnobMatch::Controller)
+        );
+        assert_eq!(knob.matches("APC Key 25", 1, &numbered(20)), None);
+    }
+
+    #[test]
+    fn decrement_button_matches() {
+        let mut knob = KnobMapping::new(Some(1), numbered(96));
+        knob.decrement = Some(numbered(97));
+        assert_eq!(
+            knob.matches(PORT, 1, &numbered(96)),
+            Some(KnobMatch::Controller)
+        );
+        assert_eq!(
+            knob.matches(PORT, 1, &numbered(97)),
+            Some(KnobMatch::Decrement)
+        );
+        assert_eq!(knob.matches(PORT, 2, &numbered(97)), None);
+    }
+
+    #[test]
+    fn first_matching_knob_is_found() {
+        let knobs = vec![
+            KnobMapping::new(Some(1), numbered(20)),
+            KnobMapping::new(Some(2), numbered(20)),
+            KnobMapping::new(None, numbered(21)),
+            KnobMapping::new(None, numbered(20)),
+        ];
+        let found = |channel, n| find_knob(&knobs, PORT, channel, &numbered(n)).map(|(i, _, _)| i);
+        assert_eq!(found(1, 20), Some(0));
+        assert_eq!(found(2, 20), Some(1));
+        assert_eq!(found(3, 20), Some(3));
+        // A channel-scoped knob doesn't swallow other controllers on its channel
+        assert_eq!(found(1, 21), Some(2));
+        assert_eq!(found(1, 22), None);
+    }
+}
@@ -1,43 +1,253 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Debug)]
+use super::{chords::Chord, parameters::ParameterKind};
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct NotePayload {
     pub channel: u8,
     pub note: u8,
     pub velocity: u8,
+    /// Note name with octave, e.g. "C#4"; not required for MIDI output
+    #[serde(default)]
+    pub name: String,
+    /// 0 (C) to 11 (B)
+    #[serde(default)]
+    pub pitch_class: u8,
+    #[serde(default)]
+    pub octave: i8,
+    /// Equal-tempered frequency in Hz
+    #[serde(default)]
+    pub frequency: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ControllerLabel {
     Numbered(u8),
     Special(String),
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum MidiValue {
     LowRes(u8),
     HighRes(u16),
+    /// On/off controllers such as Hold (sustain) or Legato
+    Switch(bool),
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ControlChangePayload {
     pub channel: u8,
     pub controller: ControllerLabel,
     pub value: MidiValue,
 }
 
+/// Snapshot of every note currently sounding on the port and channel that
+/// changed, plus every other port and channel with notes sounding (the plug
+/// is retained, and only the latest message is kept for late joiners)
+#[derive(Serialize, Debug)]
+pub struct NotesHeldPayload {
+    /// Name of the MIDI input port
+    pub port: String,
+    pub channel: u8,
+    /// Sorted, lowest note first
+    pub notes: Vec<u8>,
+    pub names: Vec<String>,
+    /// Every port and channel with notes sounding, this one included
+    pub all: Vec<NotesHeld>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NotesHeld {
+    pub port: String,
+    pub channel: u8,
+    pub notes: Vec<u8>,
+    pub names: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChordPayload {
+    /// Name of the MIDI input port
+    pub port: String,
+    pub channel: u8,
+    /// None when the held notes no longer form a recognised chord
+    pub chord: Option<Chord>,
+    /// Every port and channel currently holding a recognised chord (the plug
+    /// is retained, and only the latest message is kept for late joiners)
+    pub all: Vec<ChordHeld>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChordHeld {
+    pub port: String,
+    pub channel: u8,
+    pub chord: Chord,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PitchBendPayload {
+    pub channel: u8,
+    /// Raw 14-bit value, 0-16383 with 8192 at centre
+    pub value: u16,
+    /// -1.0 (full down) to 1.0 (full up), 0.0 at centre
+    #[serde(default)]
+    pub normalised: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChannelPressurePayload {
+    pub channel: u8,
+    pub pressure: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PolyPressurePayload {
+    pub channel: u8,
+    pub note: u8,
+    pub pressure: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProgramChangePayload {
+    pub channel: u8,
+    pub program: u8,
+    /// Last selected bank on this channel (MSB and LSB combined), 0 if none
+    pub bank: u16,
+    pub bank_msb: u8,
+    pub bank_lsb: u8,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ParameterPayload {
+    pub channel: u8,
+    pub kind: ParameterKind,
+    /// 14-bit parameter number
+    pub number: u16,
+    /// 14-bit parameter value
+    pub value: u16,
+}
+
 #[derive(Serialize, Debug)]
 pub struct KnobPayload {
+    /// Name of the MIDI port (device) the knob belongs to
+    pub port: String,
+    /// Position in the device's knob mapping, plus the device's index offset
+    pub index: u8,
+    /// The knob's name from the mapping, if it has one
+    pub name: Option<String>,
+    /// 0.0-1.0 by default, or shaped by the mapping's curve and range
+    pub position: f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ButtonPayload {
+    /// Name of the MIDI port (device) the button belongs to
+    pub port: String,
+    /// Position in the device's button mapping
+    pub index: u8,
+    /// The button's name from the mapping, if it has one
+    pub name: Option<String>,
+    /// Whether the button is physically held down
+    pub pressed: bool,
+    /// Same as `pressed` for momentary buttons; the latched state for toggle
+    /// and radio-group buttons
+    pub state: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct XyPadPayload {
+    /// Name of the MIDI port (device) the pad belongs to
+    pub port: String,
+    /// The group's name from the mapping
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FaderBankPayload {
+    /// Name of the MIDI port (device) the faders belong to
+    pub port: String,
+    /// The group's name from the mapping
+    pub name: String,
+    /// In the order given in the mapping
+    pub positions: Vec<f32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EncoderPayload {
+    /// Name of the MIDI port (device) the encoder belongs to
+    pub port: String,
+    /// The group's name from the mapping
+    pub name: String,
+    pub position: f32,
+    /// Whether the push button is held down
+    pub pressed: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ControllerValue {
+    pub channel: u8,
+    pub controller: ControllerLabel,
+    /// Last value published on "controlChange" (i.e. absolute, even for
+    /// relative controllers)
+    pub value: MidiValue,
+}
+
+/// Snapshot of every remembered controller value on one port
+#[derive(Serialize, Debug)]
+pub struct ControllerValuesPayload {
+    /// Name of the MIDI input port
+    pub port: String,
+    /// Sorted by channel
+    pub values: Vec<ControllerValue>,
+}
+
+/// Which port(s) a command from Tether applies to
+#[derive(Deserialize, Debug, Default)]
+pub struct ControllerValuesRequest {
+    /// Port name or index; all ports if omitted
+    #[serde(default)]
+    pub port: Option<String>,
+}
+
+/// Move a mapped knob's stored value, e.g. on scene recall
+#[derive(Deserialize, Debug)]
+pub struct SetKnobRequest {
+    /// Port name or index; all ports if omitted
+    #[serde(default)]
+    pub port: Option<String>,
+    /// As published on "knobs", i.e. including any index offset
     pub index: u8,
+    /// Position of the knob's travel, 0.0-1.0 (before any range or curve)
     pub position: f32,
 }
 
+/// Commands received via Tether, for the model to act on
+#[derive(Debug)]
+pub enum TetherCommand {
+    ResetControllerValues(ControllerValuesRequest),
+    QueryControllerValues(ControllerValuesRequest),
+    SetKnob(SetKnobRequest),
+}
+
 #[derive(Debug, Serialize)]
 pub enum TetherMidiMessage {
     /// Already-encoded payload
     Raw(Vec<u8>),
     NoteOn(NotePayload),
     NoteOff(NotePayload),
+    NotesHeld(NotesHeldPayload),
+    Chord(ChordPayload),
     ControlChange(ControlChangePayload),
+    PitchBend(PitchBendPayload),
+    ChannelPressure(ChannelPressurePayload),
+    PolyPressure(PolyPressurePayload),
+    ProgramChange(ProgramChangePayload),
+    Parameter(ParameterPayload),
     Knob(KnobPayload),
+    Button(ButtonPayload),
+    XyPad(XyPadPayload),
+    FaderBank(FaderBankPayload),
+    Encoder(EncoderPayload),
+    ControllerValues(ControllerValuesPayload),
 }
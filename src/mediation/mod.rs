@@ -1,46 +1,110 @@
 use ::anyhow::anyhow;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
     sync::mpsc::{Receiver, Sender},
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 
 use circular_buffer::CircularBuffer;
-use log::{debug, error, warn};
-use midi_msg::{Channel, ControlChange, MidiMsg};
+use log::{debug, info, warn};
+use midi_msg::{Channel, ChannelModeMsg, ControlChange, MidiMsg};
 use rmp_serde::to_vec_named;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     mediation::messages::{ControllerLabel, NotePayload, TetherMidiMessage},
     tether_interface::TetherStateMessage,
 };
 
+pub mod buttons;
+pub mod chords;
+pub mod controllers;
+pub mod curves;
+pub mod groups;
+pub mod held_notes;
 pub mod mappings;
 pub mod messages;
+pub mod notes;
+pub mod parameters;
+pub mod relative;
+pub mod smoothing;
+pub mod tether_to_midi;
+pub mod velocity;
 
 use self::{
-    mappings::{load_knob_mappings, KnobMapping},
-    messages::{ControlChangePayload, KnobPayload, MidiValue},
+    buttons::{press_button, ButtonInput, ButtonMapping},
+    chords::{recognise_chord, Chord},
+    controllers::{
+        label_channel_mode, label_control_change, pair_high_res, HighResPair, HighResState,
+        PairedControl,
+    },
+    curves::normalise,
+    groups::{ControlGroup, GroupKind},
+    held_notes::HeldNotes,
+    mappings::{
+        find_knob, find_knob_mapping, merge_mappings, save_device_mapping, user_mappings_dir,
+        DeviceWithMapping, KnobMapping, KnobMatch,
+    },
+    messages::{
+        ButtonPayload, ChannelPressurePayload, ChordHeld, ChordPayload, ControlChangePayload,
+        ControllerValue, ControllerValuesPayload, EncoderPayload, FaderBankPayload, KnobPayload,
+        MidiValue, NotesHeld, NotesHeldPayload, ParameterPayload, PitchBendPayload,
+        PolyPressurePayload, ProgramChangePayload, SetKnobRequest, TetherCommand, XyPadPayload,
+    },
+    notes::NoteNaming,
+    parameters::{is_parameter_selection, merged_data_entry, ParameterState},
+    relative::RelativeEncoding,
+    smoothing::KnobOutput,
+    velocity::{find_note_profile, NoteProfile},
 };
 
 pub struct PortInformation {
     pub index: usize,
     pub full_name: String,
     pub last_received: SystemTime,
+    pub controller_mode: ControllerValueMode,
+    /// Mode given on the command line, for when no mapping sets one
+    pub default_controller_mode: ControllerValueMode,
+    /// Encoding used when in Relative mode
+    pub relative_encoding: RelativeEncoding,
+    /// Knob mapping for this device, if any
+    pub knobs: Vec<KnobMapping>,
+    /// Added to the index of every knob on this device
+    pub knob_index_offset: u8,
+    /// Button mapping for this device, if any
+    pub buttons: Vec<ButtonMapping>,
+    /// Knobs and buttons on this device that are published together
+    pub groups: Vec<ControlGroup>,
+    /// Numbered controllers combined into 14-bit values
+    pub high_res_pairs: Vec<HighResPair>,
+}
+
+/// The knob that the next Control Change on this port will be assigned to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LearnTarget {
+    pub port_index: usize,
+    /// May be one past the end, to add a new knob
+    pub knob_index: usize,
 }
 
 /// Port index, MIDI Message
 pub type MidiReceiverPayload = (usize, MidiMsg);
 
-#[derive(PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
 pub enum ControllerValueMode {
     Absolute,
     Relative,
 }
 
 pub const MONITOR_LOG_LENGTH: usize = 16;
+/// How close (as a fraction of travel) a knob waiting for pickup must come
+/// to the stored value, if it doesn't cross it
+pub const PICKUP_TOLERANCE: f32 = 0.02;
 pub const MAX_HIRES: u16 = 2u16.pow(14) - 128;
 pub const MAX_LORES: u8 = 127;
+pub const CENTRE_PITCH_BEND: u16 = 8192;
+pub const MAX_PITCH_BEND: u16 = 2u16.pow(14) - 1;
 // pub const HALF_HIRES: u16 = MAX_HIRES / 2;
 
 pub struct MediationDataModel {
@@ -52,9 +116,52 @@ pub struct MediationDataModel {
     pub tether_connected: bool,
     pub tether_uri: Option<String>,
     pub tether_state_rx: Receiver<TetherStateMessage>,
-    pub controller_mode: ControllerValueMode,
-    pub known_controller_values: HashMap<String, MidiValue>,
-    pub knobs: Vec<KnobMapping>,
+    pub tether_command_rx: Receiver<TetherCommand>,
+    /// Last value published for every controller, by port index, channel and
+    /// controller; also the starting point for relative controllers
+    pub known_controller_values: HashMap<(usize, u8, ControllerLabel), MidiValue>,
+    /// Last Bank Select value (MSB and LSB combined), by port index and channel
+    pub selected_banks: HashMap<(usize, u8), u16>,
+    /// Velocity curves and note filters (see `--notes.profile`)
+    pub note_profiles: Vec<NoteProfile>,
+    /// Notes whose NoteOn was dropped by a profile, so their NoteOff is
+    /// dropped too; by port index, channel and note
+    pub dropped_notes: HashSet<(usize, u8, u8)>,
+    /// State of each paired 14-bit controller, by port index and channel
+    /// (then by MSB controller number)
+    pub high_res_states: HashMap<(usize, u8), HashMap<u8, HighResState>>,
+    /// RPN/NRPN selection and values, by port index and channel
+    pub parameter_states: HashMap<(usize, u8), ParameterState>,
+    /// Latched state of toggle and radio-group buttons, by port index (then
+    /// button index)
+    pub button_states: HashMap<usize, HashMap<usize, bool>>,
+    /// Knobs waiting for pickup, by port index and knob index, with the last
+    /// physical position seen (if any)
+    pub pickup_pending: HashMap<(usize, usize), Option<f32>>,
+    /// Channel each mapped knob was last seen (or set) on, by port index and
+    /// knob index
+    pub knob_channels: HashMap<(usize, usize), u8>,
+    /// Output of smoothed or rate limited knobs, by port index and knob index
+    pub knob_outputs: HashMap<(usize, usize), KnobOutput>,
+    /// Whether each mapped button is held down, by port index and button index
+    pub buttons_held: HashMap<(usize, usize), bool>,
+    /// Currently sounding notes, by port index and channel
+    pub held_notes: HashMap<(usize, u8), HeldNotes>,
+    pub chord_detection: bool,
+    /// Last chord published, by port index and channel
+    pub last_chords: HashMap<(usize, u8), Option<Chord>>,
+    /// Every known device mapping, whether or not the device is connected
+    pub device_mappings: Vec<DeviceWithMapping>,
+    /// Where learned mappings are saved
+    pub mappings_save_path: Option<PathBuf>,
+    /// MIDI learn mode, if active
+    pub learning: Option<LearnTarget>,
+    /// Reloaded mappings, if the mapping files are being watched
+    pub mappings_rx: Option<Receiver<Vec<DeviceWithMapping>>>,
+    /// Give devices without an explicit knob index offset one that follows on
+    /// from the knobs of the devices already mapped
+    pub continuous_knobs: bool,
+    pub note_naming: NoteNaming,
 }
 
 impl MediationDataModel {
@@ -62,7 +169,8 @@ impl MediationDataModel {
         midi_rx: Receiver<MidiReceiverPayload>,
         tether_tx: Sender<TetherMidiMessage>,
         tether_state_rx: Receiver<TetherStateMessage>,
-        controller_mode: ControllerValueMode,
+        tether_command_rx: Receiver<TetherCommand>,
+        note_naming: NoteNaming,
     ) -> Self {
         MediationDataModel {
             midi_rx,
@@ -73,13 +181,36 @@ impl MediationDataModel {
             tether_state_rx,
             tether_connected: false,
             tether_uri: None,
-            controller_mode,
+            tether_command_rx,
             known_controller_values: HashMap::new(),
-            knobs: Vec::new(),
+            selected_banks: HashMap::new(),
+            note_profiles: Vec::new(),
+            dropped_notes: HashSet::new(),
+            high_res_states: HashMap::new(),
+            parameter_states: HashMap::new(),
+            held_notes: HashMap::new(),
+            button_states: HashMap::new(),
+            buttons_held: HashMap::new(),
+            pickup_pending: HashMap::new(),
+            knob_outputs: HashMap::new(),
+            knob_channels: HashMap::new(),
+            chord_detection: false,
+            last_chords: HashMap::new(),
+            device_mappings: Vec::new(),
+            mappings_rx: None,
+            mappings_save_path: None,
+            learning: None,
+            continuous_knobs: false,
+            note_naming,
         }
     }
 
-    pub fn add_port(&mut self, index: usize, full_name: String) {
+    pub fn add_port(
+        &mut self,
+        index: usize,
+        full_name: String,
+        controller_mode: ControllerValueMode,
+    ) {
         // let shortened_name = full_name.replace(" ", "_").trim().to_lowercase();
         let port_key = format!("{index}");
         // let full_name = String::from("unknown");
@@ -89,6 +220,14 @@ impl MediationDataModel {
                 index,
                 full_name,
                 last_received: SystemTime::now(),
+                controller_mode,
+                default_controller_mode: controller_mode,
+                relative_encoding: RelativeEncoding::default(),
+                knobs: Vec::new(),
+                knob_index_offset: 0,
+                buttons: Vec::new(),
+                groups: Vec::new(),
+                high_res_pairs: Vec::new(),
             },
         );
     }
@@ -106,138 +245,169 @@ impl MediationDataModel {
                 debug!("Channel {:?}, msg: {:?}", channel, msg);
                 match msg {
                     midi_msg::ChannelVoiceMsg::NoteOn { note, velocity } => {
-                        let out_msg = NotePayload {
-                            channel: channel_to_int(*channel),
-                            note: *note,
-                            velocity: *velocity,
+                        let Some(velocity) =
+                            self.apply_note_profile(port_index, channel, *note, *velocity, true)
+                        else {
+                            debug!("Dropped NoteOn {}, @ {}", note, velocity);
+                            self.update_port_info(port_index);
+                            return;
                         };
+                        let out_msg = self.note_payload(channel_to_int(*channel), *note, velocity);
                         self.tether_message_log.push_back(format!("{:?}", out_msg));
                         self.tether_tx
                             .send(TetherMidiMessage::NoteOn(out_msg))
                             .unwrap();
                         debug!("NoteOn {}, @ {}", note, velocity);
+                        let note = *note;
+                        self.handle_buttons(
+                            port_index,
+                            channel,
+                            ButtonInput::Note(note),
+                            velocity > 0,
+                        );
+                        // NoteOn with zero velocity is conventionally a NoteOff
+                        if velocity == 0 {
+                            self.update_held_notes(port_index, channel, |held| held.note_off(note));
+                        } else {
+                            self.update_held_notes(port_index, channel, |held| held.note_on(note));
+                        }
                     }
                     midi_msg::ChannelVoiceMsg::NoteOff { note, velocity } => {
-                        let out_msg = NotePayload {
-                            channel: channel_to_int(*channel),
-                            note: *note,
-                            velocity: *velocity,
-                        };
+                        if self
+                            .apply_note_profile(port_index, channel, *note, *velocity, false)
+                            .is_none()
+                        {
+                            debug!("Dropped NoteOff {}", note);
+                            self.update_port_info(port_index);
+                            return;
+                        }
+                        let out_msg = self.note_payload(channel_to_int(*channel), *note, *velocity);
                         self.tether_message_log.push_back(format!("{:?}", out_msg));
                         self.tether_tx
                             .send(TetherMidiMessage::NoteOff(out_msg))
                             .unwrap();
                         debug!("NoteOff {}, @ {}", note, velocity);
+                        let note = *note;
+                        self.handle_buttons(port_index, channel, ButtonInput::Note(note), false);
+                        self.update_held_notes(port_index, channel, |held| held.note_off(note));
                     }
                     midi_msg::ChannelVoiceMsg::ControlChange { control } => {
                         debug!("ControlChange message: {:?}", control);
-                        match control {
-                            ControlChange::Undefined { control, value } => {
-                                self.send_control_change(
-                                    ControllerLabel::Numbered(*control),
-                                    MidiValue::LowRes(*value),
-                                    channel,
-                                );
-                            }
-                            ControlChange::ModWheel(value) => self.send_control_change(
-                                ControllerLabel::Special("ModWheel".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Breath(value) => self.send_control_change(
-                                ControllerLabel::Special("Breath".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::UndefinedHighRes {
-                                control1,
-                                control2,
+                        if let ControlChange::BankSelect(value) = control {
+                            // The MSB arrives first, then (optionally) the LSB combined
+                            // with it; either way the latest value is the full bank
+                            self.selected_banks
+                                .insert((port_index, channel_to_int(*channel)), *value);
+                        }
+                        if let ControlChange::Hold(value) = control {
+                            let sustain = *value >= 64;
+                            self.update_held_notes(port_index, channel, |held| {
+                                held.set_sustain(sustain)
+                            });
+                        }
+                        if let Some((kind, number, value)) = self
+                            .parameter_states
+                            .entry((port_index, channel_to_int(*channel)))
+                            .or_default()
+                            .apply(control)
+                        {
+                            let out_msg = ParameterPayload {
+                                channel: channel_to_int(*channel),
+                                kind,
+                                number,
                                 value,
-                            } => self.send_control_change(
-                                ControllerLabel::Special(format!(
-                                    "UndefinedHighRes-{}-{}",
-                                    control1, control2
-                                )),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Foot(value) => self.send_control_change(
-                                ControllerLabel::Special("Foot".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Portamento(value) => self.send_control_change(
-                                ControllerLabel::Special("Portamento".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::DataEntry(value) => self.send_control_change(
-                                ControllerLabel::Special("DataEntry".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Volume(value) => self.send_control_change(
-                                ControllerLabel::Special("Volume".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Balance(value) => self.send_control_change(
-                                ControllerLabel::Special("Balance".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Pan(value) => self.send_control_change(
-                                ControllerLabel::Special("Pan".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Expression(value) => self.send_control_change(
-                                ControllerLabel::Special("Expression".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Effect1(value) => self.send_control_change(
-                                ControllerLabel::Special("Effect1".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::Effect2(value) => self.send_control_change(
-                                ControllerLabel::Special("Effect2".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::GeneralPurpose1(value) => self.send_control_change(
-                                ControllerLabel::Special("GeneralPurpose1".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::GeneralPurpose2(value) => self.send_control_change(
-                                ControllerLabel::Special("GeneralPurpose2".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::GeneralPurpose3(value) => self.send_control_change(
-                                ControllerLabel::Special("GeneralPurpose3".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-                            ControlChange::GeneralPurpose4(value) => self.send_control_change(
-                                ControllerLabel::Special("GeneralPurpose4".into()),
-                                MidiValue::HighRes(*value),
-                                channel,
-                            ),
-
-                            // -------------------------------------------------------------
-                            _ => {
-                                warn!("This type of ControlChange message not handled (yet)");
+                            };
+                            self.tether_message_log.push_back(format!("{:?}", out_msg));
+                            self.tether_tx
+                                .send(TetherMidiMessage::Parameter(out_msg))
+                                .unwrap();
+                        }
+                        // Parameter selection has no single controller value, so only
+                        // appears on the "parameter" plug, along with any Data Entry
+                        // combined with it
+                        let labelled = match self.pair_high_res(port_index, channel, control) {
+                            PairedControl::Unpaired if is_parameter_selection(control) => {
+                                merged_data_entry(control)
+                                    .and_then(|entry| label_control_change(&entry))
+                            }
+                            PairedControl::Unpaired => label_control_change(control),
+                            PairedControl::Waiting => None,
+                            PairedControl::Complete(msb, value) => {
+                                Some((ControllerLabel::Numbered(msb), MidiValue::HighRes(value)))
                             }
+                        };
+                        if let Some((label, value)) = labelled {
+                            self.send_control_change(port_index, label, value, channel);
                         }
                     }
+                    midi_msg::ChannelVoiceMsg::PitchBend { bend } => {
+                        let out_msg = PitchBendPayload {
+                            channel: channel_to_int(*channel),
+                            value: *bend,
+                            normalised: normalise_pitch_bend(*bend),
+                        };
+                        self.tether_message_log.push_back(format!("{:?}", out_msg));
+                        self.tether_tx
+                            .send(TetherMidiMessage::PitchBend(out_msg))
+                            .unwrap();
+                        debug!("PitchBend {}", bend);
+                    }
+                    midi_msg::ChannelVoiceMsg::ChannelPressure { pressure } => {
+                        let out_msg = ChannelPressurePayload {
+                            channel: channel_to_int(*channel),
+                            pressure: *pressure,
+                        };
+                        self.tether_message_log.push_back(format!("{:?}", out_msg));
+                        self.tether_tx
+                            .send(TetherMidiMessage::ChannelPressure(out_msg))
+                            .unwrap();
+                        debug!("ChannelPressure {}", pressure);
+                    }
+                    midi_msg::ChannelVoiceMsg::PolyPressure { note, pressure } => {
+                        let out_msg = PolyPressurePayload {
+                            channel: channel_to_int(*channel),
+                            note: *note,
+                            pressure: *pressure,
+                        };
+                        self.tether_message_log.push_back(format!("{:?}", out_msg));
+                        self.tether_tx
+                            .send(TetherMidiMessage::PolyPressure(out_msg))
+                            .unwrap();
+                        debug!("PolyPressure {}, @ {}", note, pressure);
+                    }
+                    midi_msg::ChannelVoiceMsg::ProgramChange { program } => {
+                        let channel = channel_to_int(*channel);
+                        let bank = self
+                            .selected_banks
+                            .get(&(port_index, channel))
+                            .copied()
+                            .unwrap_or(0);
+                        let out_msg = ProgramChangePayload {
+                            channel,
+                            program: *program,
+                            bank,
+                            bank_msb: (bank >> 7) as u8,
+                            bank_lsb: (bank & 0x7F) as u8,
+                        };
+                        self.tether_message_log.push_back(format!("{:?}", out_msg));
+                        self.tether_tx
+                            .send(TetherMidiMessage::ProgramChange(out_msg))
+                            .unwrap();
+                        debug!("ProgramChange {}, bank {}", program, bank);
+                    }
                     _ => {
                         warn!("This type of ChannelVoiceMessage not handled (yet)");
                     }
                 }
             }
+            MidiMsg::ChannelMode { channel, msg } => {
+                debug!("Channel {:?}, mode msg: {:?}", channel, msg);
+                if let ChannelModeMsg::AllNotesOff | ChannelModeMsg::AllSoundOff = msg {
+                    self.update_held_notes(port_index, channel, |held| held.clear());
+                }
+                let (label, value) = label_channel_mode(msg);
+                self.send_control_change(port_index, label, value, channel);
+            }
             _ => {
                 debug!("unhandled midi message: {:?}", msg);
             }
@@ -245,99 +415,802 @@ impl MediationDataModel {
         self.update_port_info(port_index);
     }
 
+    /// Apply the note profile for this port and channel, if any: the
+    /// velocity to publish, or None if the note is dropped. NoteOffs (and
+    /// NoteOns with zero velocity) are dropped only if their NoteOn was.
+    fn apply_note_profile(
+        &mut self,
+        port_index: usize,
+        channel: &Channel,
+        note: u8,
+        velocity: u8,
+        is_note_on: bool,
+    ) -> Option<u8> {
+        let key = (port_index, channel_to_int(*channel), note);
+        if !is_note_on || velocity == 0 {
+            return if self.dropped_notes.remove(&key) {
+                None
+            } else {
+                Some(velocity)
+            };
+        }
+        let port_name = self.port_name(port_index);
+        let Some(profile) =
+            find_note_profile(&self.note_profiles, &port_name, channel_to_int(*channel))
+        else {
+            return Some(velocity);
+        };
+        match profile.note_on(note, velocity) {
+            Some(velocity) => {
+                self.dropped_notes.remove(&key);
+                Some(velocity)
+            }
+            None => {
+                self.dropped_notes.insert(key);
+                None
+            }
+        }
+    }
+
+    fn note_payload(&self, channel: u8, note: u8, velocity: u8) -> NotePayload {
+        NotePayload {
+            channel,
+            note,
+            velocity,
+            name: self.note_naming.name(note),
+            pitch_class: self.note_naming.pitch_class(note),
+            octave: self.note_naming.octave(note),
+            frequency: self.note_naming.frequency(note),
+        }
+    }
+
+    /// Apply an update to the held notes for this port and channel, and publish
+    /// the full snapshot if it changed
+    fn update_held_notes(
+        &mut self,
+        port_index: usize,
+        channel: &Channel,
+        update: impl FnOnce(&mut HeldNotes) -> bool,
+    ) {
+        let channel = channel_to_int(*channel);
+        let held = self.held_notes.entry((port_index, channel)).or_default();
+        if !update(held) {
+            return;
+        }
+        let notes = held.notes();
+        let mut sounding: Vec<(&(usize, u8), Vec<u8>)> = self
+            .held_notes
+            .iter()
+            .map(|(key, held)| (key, held.notes()))
+            .filter(|(_, notes)| !notes.is_empty())
+            .collect();
+        sounding.sort_by_key(|(key, _)| **key);
+        let all = sounding
+            .into_iter()
+            .map(|((port_index, channel), notes)| NotesHeld {
+                port: self.port_name(*port_index),
+                channel: *channel,
+                names: notes.iter().map(|n| self.note_naming.name(*n)).collect(),
+                notes,
+            })
+            .collect();
+        let out_msg = NotesHeldPayload {
+            port: self.port_name(port_index),
+            channel,
+            names: notes.iter().map(|n| self.note_naming.name(*n)).collect(),
+            notes: notes.clone(),
+            all,
+        };
+        self.tether_message_log.push_back(format!("{:?}", out_msg));
+        self.tether_tx
+            .send(TetherMidiMessage::NotesHeld(out_msg))
+            .unwrap();
+
+        if self.chord_detection {
+            let chord = recognise_chord(&notes);
+            let last_chord = self.last_chords.entry((port_index, channel)).or_default();
+            if *last_chord != chord {
+                *last_chord = chord.clone();
+                let mut chords: Vec<(&(usize, u8), &Chord)> = self
+                    .last_chords
+                    .iter()
+                    .filter_map(|(key, chord)| chord.as_ref().map(|chord| (key, chord)))
+                    .collect();
+                chords.sort_by_key(|(key, _)| **key);
+                let all = chords
+                    .into_iter()
+                    .map(|((port_index, channel), chord)| ChordHeld {
+                        port: self.port_name(*port_index),
+                        channel: *channel,
+                        chord: chord.clone(),
+                    })
+                    .collect();
+                let out_msg = ChordPayload {
+                    port: self.port_name(port_index),
+                    channel,
+                    chord,
+                    all,
+                };
+                self.tether_message_log.push_back(format!("{:?}", out_msg));
+                self.tether_tx
+                    .send(TetherMidiMessage::Chord(out_msg))
+                    .unwrap();
+            }
+        }
+    }
+
+    fn port_name(&self, index: usize) -> String {
+        match self.ports_metadata.get(&format!("{index}")) {
+            Some(info) => info.full_name.clone(),
+            None => format!("{index}"),
+        }
+    }
+
     fn send_control_change(
         &mut self,
+        port_index: usize,
         control_label: ControllerLabel,
         value: MidiValue,
         channel: &Channel,
     ) {
-        let send_absolute_value: MidiValue = match self.controller_mode {
-            ControllerValueMode::Relative => {
-                let key = match &control_label {
-                    ControllerLabel::Numbered(value) => value.to_string(),
-                    ControllerLabel::Special(label) => label.clone(),
-                };
-                match value {
-                    MidiValue::LowRes(x) => {
-                        if let Some(prev_value) = self.known_controller_values.get(&key) {
-                            let prev_value = match prev_value {
-                                MidiValue::HighRes(x) => {
-                                    error!("This value used to be u8");
-                                    *x as u8
-                                }
-                                MidiValue::LowRes(x) => *x,
-                            };
-                            let increment = if x < 64 { x as i16 } else { x as i16 - 128 };
-                            let absolute_value: i16 = (prev_value as i16 + increment).clamp(0, 127);
-                            let absolute_value: u8 = absolute_value.try_into().unwrap_or(0);
-                            self.known_controller_values
-                                .insert(key, MidiValue::LowRes(absolute_value));
-                            MidiValue::LowRes(absolute_value)
-                        } else {
-                            let last_known_value = MidiValue::LowRes(x);
-                            self.known_controller_values.insert(key, last_known_value);
-                            MidiValue::LowRes(x)
-                        }
-                    }
-                    MidiValue::HighRes(x) => {
-                        debug!("HiRes relative ignored (send as-is)");
-                        MidiValue::HighRes(x)
-                    }
-                }
+        if let Some(target) = self.learning {
+            if target.port_index == port_index {
+                self.learn_knob(target, &control_label, channel);
+            }
+        }
+
+        let button_pressed = match &value {
+            MidiValue::LowRes(x) => *x > 0,
+            MidiValue::HighRes(x) => *x > 0,
+            MidiValue::Switch(on) => *on,
+        };
+        self.handle_buttons(
+            port_index,
+            channel,
+            ButtonInput::Controller(&control_label),
+            button_pressed,
+        );
+
+        let port_name = self.port_name(port_index);
+        let matched_knob = self
+            .ports_metadata
+            .get(&format!("{port_index}"))
+            .and_then(|info| {
+                find_knob(
+                    &info.knobs,
+                    &port_name,
+                    channel_to_int(*channel),
+                    &control_label,
+                )
+                .map(|(i, knob, knob_match)| {
+                    (
+                        i,
+                        (i as u8).saturating_add(info.knob_index_offset),
+                        knob.clone(),
+                        knob_match,
+                    )
+                })
+            });
+
+        // A decrement button stands in for the knob's own controller
+        let is_decrement = matched_knob
+            .as_ref()
+            .is_some_and(|(_, _, _, knob_match)| *knob_match == KnobMatch::Decrement);
+        let control_label = match &matched_knob {
+            Some((_, _, knob, _)) if is_decrement => knob.controller.clone(),
+            _ => control_label,
+        };
+
+        let encoding =
+            self.relative_encoding_for(port_index, matched_knob.as_ref().map(|(_, _, k, _)| k));
+        // Releasing an increment/decrement button changes nothing
+        if encoding == Some(RelativeEncoding::IncDecButtons) && !button_pressed {
+            return;
+        }
+        let key = (port_index, channel_to_int(*channel), control_label.clone());
+        let send_absolute_value: MidiValue = match encoding {
+            Some(encoding) => {
+                encoding.apply(self.known_controller_values.get(&key), &value, is_decrement)
             }
-            ControllerValueMode::Absolute => value,
+            None => value,
         };
 
+        if let Some((local_index, _, _, _)) = &matched_knob {
+            if encoding.is_some() {
+                // e.g. the port was switched to Relative mode while waiting
+                self.pickup_pending.remove(&(port_index, *local_index));
+            } else if !self.pick_up(port_index, *local_index, &send_absolute_value) {
+                debug!("Knob #{} waiting for pickup", local_index);
+                return;
+            }
+            self.knob_channels
+                .insert((port_index, *local_index), channel_to_int(*channel));
+        }
+
+        let previous = self
+            .known_controller_values
+            .insert(key, send_absolute_value.clone());
+
         // ControlChange Message...
         let out_msg = ControlChangePayload {
             channel: channel_to_int(*channel),
             controller: control_label.clone(),
             value: send_absolute_value.clone(),
         };
+
+        // If applicable, Knob message...
+        match matched_knob {
+            Some((local_index, _, matched, _)) => {
+                debug!("Found mapping {:?}", matched);
+                self.update_knob(
+                    port_index,
+                    local_index,
+                    &matched,
+                    previous.as_ref(),
+                    &send_absolute_value,
+                    Some(out_msg),
+                );
+            }
+            None => self.publish_control_change(out_msg),
+        }
+    }
+
+    fn pair_high_res(
+        &mut self,
+        port_index: usize,
+        channel: &Channel,
+        control: &ControlChange,
+    ) -> PairedControl {
+        let Some(info) = self.ports_metadata.get(&format!("{port_index}")) else {
+            return PairedControl::Unpaired;
+        };
+        if info.high_res_pairs.is_empty() {
+            return PairedControl::Unpaired;
+        }
+        let msbs = self
+            .high_res_states
+            .entry((port_index, channel_to_int(*channel)))
+            .or_default();
+        pair_high_res(&info.high_res_pairs, msbs, control)
+    }
+
+    fn publish_control_change(&mut self, out_msg: ControlChangePayload) {
         self.tether_message_log.push_back(format!("{:?}", out_msg));
         self.tether_tx
             .send(TetherMidiMessage::ControlChange(out_msg))
             .unwrap();
+    }
 
-        // If applicable, Knob message...
-        if !self.knobs.is_empty() {
-            if let Some((i, matched)) = self.knobs.iter().enumerate().find(|(_index, knob)| {
-                if let Some(spec_channel) = knob.channel {
-                    return spec_channel == channel_to_int(*channel);
+    /// Publish a knob's new value (and the Control Change it came from, if
+    /// any), straight away or, if the knob is smoothed or rate limited, as
+    /// its output is next updated
+    fn update_knob(
+        &mut self,
+        port_index: usize,
+        local_index: usize,
+        knob: &KnobMapping,
+        previous: Option<&MidiValue>,
+        value: &MidiValue,
+        control_change: Option<ControlChangePayload>,
+    ) {
+        let position = normalise(value);
+        if !knob.is_timed() {
+            self.knob_outputs.remove(&(port_index, local_index));
+            if let Some(control_change) = control_change {
+                self.publish_control_change(control_change);
+            }
+            self.publish_knob(port_index, local_index, position);
+            return;
+        }
+        let now = Instant::now();
+        let output = self
+            .knob_outputs
+            .entry((port_index, local_index))
+            .or_insert_with(|| KnobOutput::new(previous.map(normalise).unwrap_or(position), now));
+        output.set_target(position, now);
+        match control_change {
+            Some(control_change) if knob.rate_limit_ms.is_some() => {
+                output.pending_control_change = Some(control_change);
+            }
+            Some(control_change) => self.publish_control_change(control_change),
+            None => {}
+        }
+        self.advance_knob_output(port_index, local_index, now);
+    }
+
+    /// Move smoothed knobs towards their latest values, and publish any
+    /// changes that are due; call this regularly
+    pub fn update_knob_outputs(&mut self) {
+        let now = Instant::now();
+        let active: Vec<(usize, usize)> = self
+            .knob_outputs
+            .iter()
+            .filter(|(_, output)| output.needs_update())
+            .map(|(key, _)| *key)
+            .collect();
+        for (port_index, local_index) in active {
+            self.advance_knob_output(port_index, local_index, now);
+        }
+    }
+
+    fn advance_knob_output(&mut self, port_index: usize, local_index: usize, now: Instant) {
+        let Some(knob) = self
+            .ports_metadata
+            .get(&format!("{port_index}"))
+            .and_then(|info| info.knobs.get(local_index))
+            .cloned()
+        else {
+            self.knob_outputs.remove(&(port_index, local_index));
+            return;
+        };
+        let Some(output) = self.knob_outputs.get_mut(&(port_index, local_index)) else {
+            return;
+        };
+        output.step(knob.smoothing, now);
+        if !output.is_due(knob.rate_limit_ms.map(Duration::from_millis), now) {
+            return;
+        }
+        let control_change = output.pending_control_change.take();
+        let position = output.take_unpublished();
+        if control_change.is_some() || position.is_some() {
+            output.mark_published(now);
+        }
+        if let Some(control_change) = control_change {
+            self.publish_control_change(control_change);
+        }
+        if let Some(position) = position {
+            self.publish_knob(port_index, local_index, position);
+        }
+    }
+
+    /// Publish a knob's normalised position, and any groups it is in
+    fn publish_knob(&mut self, port_index: usize, local_index: usize, position: f32) {
+        let Some(info) = self.ports_metadata.get(&format!("{port_index}")) else {
+            return;
+        };
+        let Some(knob) = info.knobs.get(local_index) else {
+            return;
+        };
+        let knob_msg = KnobPayload {
+            port: info.full_name.clone(),
+            index: (local_index as u8).saturating_add(info.knob_index_offset),
+            name: knob.name.clone(),
+            position: knob.output_at(position),
+        };
+        self.tether_message_log
+            .push_back(format!("{:?}", &knob_msg));
+        self.tether_tx
+            .send(TetherMidiMessage::Knob(knob_msg))
+            .unwrap();
+        self.publish_groups(port_index, |group| group.has_knob(local_index));
+    }
+
+    /// For knobs waiting for pickup, whether this physical value has reached
+    /// (or crossed) the stored value; always true otherwise
+    fn pick_up(&mut self, port_index: usize, local_index: usize, value: &MidiValue) -> bool {
+        let Some(last_physical) = self.pickup_pending.get(&(port_index, local_index)).copied()
+        else {
+            return true;
+        };
+        let Some(stored) = self.knob_value(port_index, local_index).map(normalise) else {
+            self.pickup_pending.remove(&(port_index, local_index));
+            return true;
+        };
+        let physical = normalise(value);
+        let crossed =
+            last_physical.is_some_and(|last| (last - stored) * (physical - stored) <= 0.0);
+        if crossed || (physical - stored).abs() <= PICKUP_TOLERANCE {
+            info!("Knob #{} picked up", local_index);
+            self.pickup_pending.remove(&(port_index, local_index));
+            true
+        } else {
+            self.pickup_pending
+                .insert((port_index, local_index), Some(physical));
+            false
+        }
+    }
+
+    /// Set the stored value of a mapped knob, and publish it; absolute knobs
+    /// with pickup enabled then wait for the physical knob to catch up
+    fn set_knob(&mut self, request: &SetKnobRequest) {
+        let position = request.position.clamp(0.0, 1.0);
+        for port_index in self.ports_matching(&request.port) {
+            let Some(info) = self.ports_metadata.get(&format!("{port_index}")) else {
+                continue;
+            };
+            let Some(local_index) = (request.index as usize)
+                .checked_sub(info.knob_index_offset as usize)
+                .filter(|i| *i < info.knobs.len())
+            else {
+                continue;
+            };
+            let knob = info.knobs[local_index].clone();
+            // Keep the channel and resolution already seen for this knob, if
+            // any; the physical knob is compared against this value for pickup,
+            // whatever channel it then arrives on
+            let channel = self
+                .knob_channels
+                .get(&(port_index, local_index))
+                .copied()
+                .or(knob.channel)
+                .unwrap_or(1);
+            self.knob_channels
+                .insert((port_index, local_index), channel);
+            let key = (port_index, channel, knob.controller.clone());
+            let value = match self.known_controller_values.get(&key) {
+                Some(MidiValue::HighRes(_)) => {
+                    MidiValue::HighRes((position * MAX_HIRES as f32).round() as u16)
                 }
-                match &knob.controller {
-                    ControllerLabel::Numbered(channel_number) => {
-                        if let ControllerLabel::Numbered(n) = &control_label {
-                            n == channel_number
-                        } else {
-                            false
-                        }
+                Some(MidiValue::Switch(_)) => MidiValue::Switch(position >= 0.5),
+                _ => MidiValue::LowRes((position * MAX_LORES as f32).round() as u8),
+            };
+            let previous = self.known_controller_values.insert(key, value.clone());
+            // Relative controls follow on from the stored value, so there is
+            // nothing to pick up
+            if knob.pickup
+                && self
+                    .relative_encoding_for(port_index, Some(&knob))
+                    .is_none()
+            {
+                self.pickup_pending.insert((port_index, local_index), None);
+            }
+            self.update_knob(
+                port_index,
+                local_index,
+                &knob,
+                previous.as_ref(),
+                &value,
+                None,
+            );
+        }
+    }
+
+    /// Publish the state of every button on this port matching the input
+    fn handle_buttons(
+        &mut self,
+        port_index: usize,
+        channel: &Channel,
+        input: ButtonInput,
+        pressed: bool,
+    ) {
+        let port_name = self.port_name(port_index);
+        let Some(info) = self.ports_metadata.get(&format!("{port_index}")) else {
+            return;
+        };
+        let latched = self.button_states.entry(port_index).or_default();
+        let channel = channel_to_int(*channel);
+        let changes: Vec<(usize, bool, bool)> = info
+            .buttons
+            .iter()
+            .enumerate()
+            .filter(|(_, button)| button.matches(&port_name, channel, &input))
+            .flat_map(|(i, _)| press_button(&info.buttons, latched, i, pressed))
+            .collect();
+        let mut changed_buttons = Vec::new();
+        for (index, pressed, state) in changes {
+            self.buttons_held.insert((port_index, index), pressed);
+            changed_buttons.push(index);
+            let out_msg = ButtonPayload {
+                port: port_name.clone(),
+                index: index as u8,
+                name: info.buttons[index].name.clone(),
+                pressed,
+                state,
+            };
+            self.tether_message_log.push_back(format!("{:?}", out_msg));
+            self.tether_tx
+                .send(TetherMidiMessage::Button(out_msg))
+                .unwrap();
+        }
+        self.publish_groups(port_index, |group| {
+            changed_buttons.iter().any(|b| group.has_button(*b))
+        });
+    }
+
+    /// Publish every group on this port selected by the filter, with the
+    /// latest values of all its members
+    fn publish_groups(&mut self, port_index: usize, filter: impl Fn(&ControlGroup) -> bool) {
+        let Some(info) = self.ports_metadata.get(&format!("{port_index}")) else {
+            return;
+        };
+        let port = info.full_name.clone();
+        let knob_output = |index: usize| {
+            info.knobs.get(index).map(|knob| {
+                match (
+                    self.knob_outputs.get(&(port_index, index)),
+                    self.knob_value(port_index, index),
+                ) {
+                    (Some(output), _) => knob.output_at(output.current),
+                    (None, Some(value)) => knob.output(value),
+                    (None, None) => knob.output(&MidiValue::LowRes(0)),
+                }
+            })
+        };
+        let mut messages = Vec::new();
+        for group in info.groups.iter().filter(|group| filter(group)) {
+            let name = group.name.clone();
+            let port = port.clone();
+            let message = match &group.kind {
+                GroupKind::XyPad { x, y } => knob_output(*x)
+                    .zip(knob_output(*y))
+                    .map(|(x, y)| TetherMidiMessage::XyPad(XyPadPayload { port, name, x, y })),
+                GroupKind::FaderBank { knobs } => knobs
+                    .iter()
+                    .map(|k| knob_output(*k))
+                    .collect::<Option<Vec<f32>>>()
+                    .map(|positions| {
+                        TetherMidiMessage::FaderBank(FaderBankPayload {
+                            port,
+                            name,
+                            positions,
+                        })
+                    }),
+                GroupKind::EncoderWithPush { knob, button } => knob_output(*knob).map(|position| {
+                    TetherMidiMessage::Encoder(EncoderPayload {
+                        port,
+                        name,
+                        position,
+                        pressed: self
+                            .buttons_held
+                            .get(&(port_index, *button))
+                            .copied()
+                            .unwrap_or(false),
+                    })
+                }),
+            };
+            match message {
+                Some(message) => messages.push(message),
+                None => warn!(
+                    "Group \"{}\" refers to a knob that isn't in the mapping",
+                    group.name
+                ),
+            }
+        }
+        for message in messages {
+            self.tether_message_log.push_back(format!("{:?}", message));
+            self.tether_tx.send(message).unwrap();
+        }
+    }
+
+    /// The encoding to decode this controller's values with, or None if they
+    /// are absolute: the knob mapping's settings win, then the port's
+    fn relative_encoding_for(
+        &self,
+        port_index: usize,
+        knob: Option<&KnobMapping>,
+    ) -> Option<RelativeEncoding> {
+        let (port_mode, port_encoding) = self
+            .ports_metadata
+            .get(&format!("{port_index}"))
+            .map(|info| (info.controller_mode, info.relative_encoding))
+            .unwrap_or((ControllerValueMode::Absolute, RelativeEncoding::default()));
+        let knob_mode = knob.and_then(|knob| {
+            knob.mode
+                .or(knob.relative.map(|_| ControllerValueMode::Relative))
+        });
+        match knob_mode.unwrap_or(port_mode) {
+            ControllerValueMode::Relative => {
+                Some(knob.and_then(|knob| knob.relative).unwrap_or(port_encoding))
+            }
+            ControllerValueMode::Absolute => None,
+        }
+    }
+
+    /// Every remembered controller value on this port
+    pub fn controller_values_snapshot(&self, port_index: usize) -> ControllerValuesPayload {
+        let mut values: Vec<ControllerValue> = self
+            .known_controller_values
+            .iter()
+            .filter(|((port, _, _), _)| *port == port_index)
+            .map(|((_, channel, controller), value)| ControllerValue {
+                channel: *channel,
+                controller: controller.clone(),
+                value: value.clone(),
+            })
+            .collect();
+        values.sort_by_key(|v| (v.channel, format!("{:?}", v.controller)));
+        ControllerValuesPayload {
+            port: self.port_name(port_index),
+            values,
+        }
+    }
+
+    /// Forget every controller value on this port, so that relative
+    /// controllers start again from zero, along with the knob state based
+    /// on those values
+    pub fn reset_controller_values(&mut self, port_index: usize) {
+        self.known_controller_values
+            .retain(|(port, _, _), _| *port != port_index);
+        self.knob_outputs.retain(|(port, _), _| *port != port_index);
+        self.knob_channels
+            .retain(|(port, _), _| *port != port_index);
+        self.pickup_pending
+            .retain(|(port, _), _| *port != port_index);
+        info!(
+            "Reset controller values for port \"{}\"",
+            self.port_name(port_index)
+        );
+    }
+
+    pub fn handle_tether_commands(&mut self) {
+        while let Ok(command) = self.tether_command_rx.try_recv() {
+            debug!("Model received command via Tether: {:?}", &command);
+            match command {
+                TetherCommand::ResetControllerValues(request) => {
+                    for port_index in self.ports_matching(&request.port) {
+                        self.reset_controller_values(port_index);
+                        self.publish_controller_values(port_index);
                     }
-                    ControllerLabel::Special(controller_name) => {
-                        if let ControllerLabel::Special(n) = &control_label {
-                            n == controller_name
-                        } else {
-                            false
-                        }
+                }
+                TetherCommand::QueryControllerValues(request) => {
+                    for port_index in self.ports_matching(&request.port) {
+                        self.publish_controller_values(port_index);
                     }
                 }
-            }) {
-                debug!("Found mapping {:?}", matched);
-                let position: f32 = match send_absolute_value {
-                    MidiValue::LowRes(x) => x as f32 / MAX_LORES as f32,
-                    MidiValue::HighRes(x) => (x as f32) / (MAX_HIRES as f32),
-                };
-                let knob_msg = KnobPayload {
-                    index: i as u8,
-                    position,
-                };
-                self.tether_message_log
-                    .push_back(format!("{:?}", &knob_msg));
-                self.tether_tx
-                    .send(TetherMidiMessage::Knob(knob_msg))
-                    .unwrap();
+                TetherCommand::SetKnob(request) => self.set_knob(&request),
+            }
+        }
+    }
+
+    fn publish_controller_values(&mut self, port_index: usize) {
+        let out_msg = self.controller_values_snapshot(port_index);
+        self.tether_message_log.push_back(format!("{:?}", out_msg));
+        self.tether_tx
+            .send(TetherMidiMessage::ControllerValues(out_msg))
+            .unwrap();
+    }
+
+    /// Port indexes named (by full name or index), or all
+    fn ports_matching(&self, port: &Option<String>) -> Vec<usize> {
+        let mut ports: Vec<usize> = self
+            .ports_metadata
+            .iter()
+            .filter(|(key, info)| match port {
+                Some(port) => *key == port || info.full_name == *port,
+                None => true,
+            })
+            .map(|(_, info)| info.index)
+            .collect();
+        if ports.is_empty() {
+            warn!("No port matching {:?}", port);
+        }
+        ports.sort();
+        ports
+    }
+
+    /// Apply any reloaded mappings to the connected devices whose mapping
+    /// changed; MIDI connections are unaffected
+    pub fn handle_mapping_updates(&mut self) {
+        let Some(mappings_rx) = &self.mappings_rx else {
+            return;
+        };
+        let Some(device_mappings) = mappings_rx.try_iter().last() else {
+            return;
+        };
+        let previous_mappings = std::mem::replace(&mut self.device_mappings, device_mappings);
+        let mut ports: Vec<(usize, String)> = self
+            .ports_metadata
+            .values()
+            .map(|info| (info.index, info.full_name.clone()))
+            .collect();
+        ports.sort();
+        for (port_index, name) in ports {
+            let previous = previous_mappings.iter().find(|d| d.name == name);
+            let current = self.device_mappings.iter().find(|d| d.name == name);
+            if previous == current {
+                continue;
+            }
+            let removed = current.is_none();
+            // Any mode chosen in the GUI is kept, unless the mapping set it
+            if previous.is_some_and(|d| d.mode.is_some() || d.relative.is_some()) {
+                self.restore_default_mode(port_index);
+            }
+            if removed {
+                self.clear_mapping(port_index);
+                info!("Removed knob mapping for device \"{}\"", name);
+            } else {
+                match self.add_knob_mapping(port_index, &name) {
+                    Ok(()) => info!("Remapped device \"{}\"", name),
+                    Err(e) => warn!("Failed to remap device \"{}\": {}", name, e),
+                }
+            }
+        }
+    }
+
+    fn learn_knob(
+        &mut self,
+        target: LearnTarget,
+        control_label: &ControllerLabel,
+        channel: &Channel,
+    ) {
+        let Some(info) = self
+            .ports_metadata
+            .get_mut(&format!("{}", target.port_index))
+        else {
+            return;
+        };
+        let learned = KnobMapping::new(Some(channel_to_int(*channel)), control_label.clone());
+        info!(
+            "Learned knob #{} on \"{}\": {:?}",
+            target.knob_index, info.full_name, learned
+        );
+        match info.knobs.get_mut(target.knob_index) {
+            // Keep the name, range etc. of a knob being re-learned
+            Some(knob) => {
+                knob.channel = learned.channel;
+                knob.controller = learned.controller;
             }
+            None => info.knobs.push(learned),
         }
+        self.learning = None;
+    }
+
+    /// The current knobs for this port as a mapping for its device, keeping
+    /// any device-wide settings from the existing mapping
+    pub fn current_device_mapping(&self, port_index: usize) -> anyhow::Result<DeviceWithMapping> {
+        let info = self
+            .ports_metadata
+            .get(&format!("{port_index}"))
+            .ok_or(anyhow!("No port with index {}", port_index))?;
+        Ok(
+            match self
+                .device_mappings
+                .iter()
+                .find(|d| d.name == info.full_name)
+            {
+                Some(existing) => DeviceWithMapping {
+                    knobs: info.knobs.clone(),
+                    buttons: info.buttons.clone(),
+                    groups: info.groups.clone(),
+                    ..existing.clone()
+                },
+                None => DeviceWithMapping {
+                    name: info.full_name.clone(),
+                    knobs: info.knobs.clone(),
+                    buttons: info.buttons.clone(),
+                    groups: info.groups.clone(),
+                    relative: None,
+                    mode: None,
+                    index_offset: None,
+                    high_res: info.high_res_pairs.clone(),
+                },
+            },
+        )
+    }
+
+    /// Save the current knobs for this port as the mapping for its device
+    pub fn save_knob_mapping(&mut self, port_index: usize) -> anyhow::Result<PathBuf> {
+        let device = self.current_device_mapping(port_index)?;
+        let target = match &self.mappings_save_path {
+            Some(path) => path.clone(),
+            None => user_mappings_dir().ok_or(anyhow!("No user config directory"))?,
+        };
+        let path = save_device_mapping(&device, &target)?;
+        info!(
+            "Saved knob mapping for \"{}\" to {}",
+            device.name,
+            path.display()
+        );
+        // Already applied, so there is nothing to do when the watcher sees the change
+        merge_mappings(&mut self.device_mappings, vec![device]);
+        Ok(path)
+    }
+
+    /// Last known value for a knob on this port (by knob index), on the
+    /// channel it was last seen or set on, if any
+    pub fn knob_value(&self, port_index: usize, local_index: usize) -> Option<&MidiValue> {
+        let knob = self
+            .ports_metadata
+            .get(&format!("{port_index}"))?
+            .knobs
+            .get(local_index)?;
+        let channel = self
+            .knob_channels
+            .get(&(port_index, local_index))
+            .copied()
+            .or(knob.channel)?;
+        self.known_controller_values
+            .get(&(port_index, channel, knob.controller.clone()))
     }
 
     fn update_port_info(&mut self, index: usize) {
@@ -348,10 +1221,67 @@ impl MediationDataModel {
         }
     }
 
-    pub fn add_knob_mapping(&mut self, name: &str) -> anyhow::Result<()> {
-        match load_knob_mappings(name) {
-            Ok(knobs) => {
-                self.knobs = knobs;
+    /// Go back to the mode given on the command line for this port, for when
+    /// the device-wide mode set by its mapping no longer applies
+    fn restore_default_mode(&mut self, port_index: usize) {
+        if let Some(info) = self.ports_metadata.get_mut(&format!("{port_index}")) {
+            info.controller_mode = info.default_controller_mode;
+            info.relative_encoding = RelativeEncoding::default();
+        }
+    }
+
+    /// Forget the knobs, buttons etc. set by a device mapping for this port,
+    /// and the state that goes with them. The mode is left as it is, since it
+    /// may have been chosen in the GUI.
+    fn clear_mapping(&mut self, port_index: usize) {
+        if let Some(info) = self.ports_metadata.get_mut(&format!("{port_index}")) {
+            info.knobs.clear();
+            info.knob_index_offset = 0;
+            info.buttons.clear();
+            info.groups.clear();
+            info.high_res_pairs.clear();
+        }
+        self.high_res_states
+            .retain(|(port, _), _| *port != port_index);
+        self.button_states.remove(&port_index);
+        self.buttons_held.retain(|(port, _), _| *port != port_index);
+        self.pickup_pending
+            .retain(|(port, _), _| *port != port_index);
+        self.knob_outputs.retain(|(port, _), _| *port != port_index);
+        self.knob_channels
+            .retain(|(port, _), _| *port != port_index);
+    }
+
+    pub fn add_knob_mapping(&mut self, port_index: usize, name: &str) -> anyhow::Result<()> {
+        match find_knob_mapping(&self.device_mappings, name) {
+            Ok(device) => {
+                let next_offset = self
+                    .ports_metadata
+                    .values()
+                    .filter(|info| info.index != port_index)
+                    .map(|info| info.knob_index_offset as usize + info.knobs.len())
+                    .max()
+                    .unwrap_or(0);
+                self.clear_mapping(port_index);
+                let Some(info) = self.ports_metadata.get_mut(&format!("{port_index}")) else {
+                    return Err(anyhow!("No port with index {}", port_index));
+                };
+                info.knob_index_offset = match device.index_offset {
+                    Some(offset) => offset,
+                    None if self.continuous_knobs => next_offset.try_into().unwrap_or(u8::MAX),
+                    None => 0,
+                };
+                info.knobs = device.knobs;
+                info.buttons = device.buttons;
+                info.groups = device.groups;
+                info.high_res_pairs = device.high_res;
+                if let Some(encoding) = device.relative {
+                    info.controller_mode = ControllerValueMode::Relative;
+                    info.relative_encoding = encoding;
+                }
+                if let Some(mode) = device.mode {
+                    info.controller_mode = mode;
+                }
                 Ok(())
             }
             Err(e) => Err(anyhow!("Failed to load knob mapping: {}", e)),
@@ -359,6 +1289,17 @@ impl MediationDataModel {
     }
 }
 
+/// Map the 14-bit bend value onto -1.0..1.0; the range above centre is one step
+/// shorter than below, so each half is scaled separately
+fn normalise_pitch_bend(bend: u16) -> f32 {
+    let offset = bend as f32 - CENTRE_PITCH_BEND as f32;
+    if bend >= CENTRE_PITCH_BEND {
+        offset / (MAX_PITCH_BEND - CENTRE_PITCH_BEND) as f32
+    } else {
+        offset / CENTRE_PITCH_BEND as f32
+    }
+}
+
 fn channel_to_int(channel: Channel) -> u8 {
     match channel {
         Channel::Ch1 => 1,
@@ -379,3 +1320,11 @@ fn channel_to_int(channel: Channel) -> u8 {
         Channel::Ch16 => 16,
     }
 }
+
+fn int_to_channel(channel: u8) -> anyhow::Result<Channel> {
+    if (1..=16).contains(&channel) {
+        Ok(Channel::from_u8(channel - 1))
+    } else {
+        Err(anyhow!("MIDI channel {} out of range 1-16", channel))
+    }
+}
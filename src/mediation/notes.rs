@@ -0,0 +1,53 @@
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+const A4_NOTE_NUMBER: u8 = 69;
+
+/// e.g. "C#" for pitch class 1
+pub fn pitch_class_name(pitch_class: u8) -> &'static str {
+    NOTE_NAMES[(pitch_class % 12) as usize]
+}
+
+/// How MIDI note numbers are turned into names and frequencies
+#[derive(Debug, Clone)]
+pub struct NoteNaming {
+    /// Octave number given to middle C (MIDI note 60); usually 4, but some
+    /// manufacturers (e.g. Yamaha) use 3
+    pub middle_c_octave: i8,
+    /// Reference frequency for A above middle C, in Hz
+    pub a4_frequency: f32,
+}
+
+impl Default for NoteNaming {
+    fn default() -> Self {
+        NoteNaming {
+            middle_c_octave: 4,
+            a4_frequency: 440.0,
+        }
+    }
+}
+
+impl NoteNaming {
+    /// 0 (C) to 11 (B)
+    pub fn pitch_class(&self, note: u8) -> u8 {
+        note % 12
+    }
+
+    pub fn octave(&self, note: u8) -> i8 {
+        (note / 12) as i8 + self.middle_c_octave - 5
+    }
+
+    /// e.g. "C#4"
+    pub fn name(&self, note: u8) -> String {
+        format!(
+            "{}{}",
+            pitch_class_name(self.pitch_class(note)),
+            self.octave(note)
+        )
+    }
+
+    /// Equal-tempered frequency in Hz
+    pub fn frequency(&self, note: u8) -> f32 {
+        self.a4_frequency * 2f32.powf((note as f32 - A4_NOTE_NUMBER as f32) / 12.0)
+    }
+}
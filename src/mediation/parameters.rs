@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use midi_msg::ControlChange;
+use serde::Serialize;
+
+pub const NRPN_MSB: u8 = 99;
+pub const NRPN_LSB: u8 = 98;
+pub const RPN_MSB: u8 = 101;
+pub const RPN_LSB: u8 = 100;
+pub const DATA_ENTRY_MSB: u8 = 6;
+pub const DATA_ENTRY_LSB: u8 = 38;
+pub const DATA_INCREMENT: u8 = 96;
+pub const DATA_DECREMENT: u8 = 97;
+
+const MAX_PARAMETER_VALUE: u16 = 2u16.pow(14) - 1;
+
+/// Whether this is (part of) an RPN/NRPN selection, which only appears on
+/// the "parameter" plug. midi-msg passes the first CC of a selection
+/// through as `Undefined`, and combines the second with it.
+pub fn is_parameter_selection(control: &ControlChange) -> bool {
+    match control {
+        ControlChange::Parameter(_) => true,
+        ControlChange::Undefined { control, .. } => {
+            matches!(*control, NRPN_MSB | NRPN_LSB | RPN_MSB | RPN_LSB)
+        }
+        _ => false,
+    }
+}
+
+/// The Data Entry that midi-msg may have combined into a parameter
+/// selection, as a message of its own
+pub fn merged_data_entry(control: &ControlChange) -> Option<ControlChange> {
+    let ControlChange::Parameter(_) = control else {
+        return None;
+    };
+    let bytes = control.to_midi_running();
+    let entry = |data_entry: u8| {
+        bytes
+            .chunks_exact(2)
+            .find(|pair| pair[0] == data_entry)
+            .map(|pair| pair[1])
+    };
+    let msb = entry(DATA_ENTRY_MSB)?;
+    let lsb = entry(DATA_ENTRY_LSB).unwrap_or(0);
+    Some(ControlChange::DataEntry(((msb as u16) << 7) | lsb as u16))
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParameterKind {
+    Registered,
+    NonRegistered,
+}
+
+/// Which (if any) RPN/NRPN is currently selected on a single channel, plus
+/// the last known value of every parameter that has been set, so that
+/// Data Increment/Decrement can be applied
+#[derive(Default, Debug)]
+pub struct ParameterState {
+    kind: Option<ParameterKind>,
+    number_msb: u8,
+    number_lsb: u8,
+    values: HashMap<(ParameterKind, u16), u16>,
+}
+
+impl ParameterState {
+    /// Apply a Control Change message to this channel's state; returns
+    /// Some(kind, number, value) if a parameter value was changed
+    pub fn apply(&mut self, control: &ControlChange) -> Option<(ParameterKind, u16, u16)> {
+        match control {
+            // midi-msg may already have combined the selection (and possibly
+            // data entry) into one message; break it back down into plain CCs
+            ControlChange::Parameter(_) => control
+                .to_midi_running()
+                .chunks_exact(2)
+                .fold(None, |changed, pair| {
+                    self.apply_cc(pair[0], pair[1]).or(changed)
+                }),
+            ControlChange::DataEntry(value) => self.set_value(*value),
+            ControlChange::DataEntry2(msb, lsb) => {
+                self.set_value(((*msb as u16) << 7) | *lsb as u16)
+            }
+            ControlChange::DataIncrement(step) => self.step_value(*step, true),
+            ControlChange::DataDecrement(step) => self.step_value(*step, false),
+            ControlChange::Undefined { control, value } => self.apply_cc(*control, *value),
+            _ => None,
+        }
+    }
+
+    fn apply_cc(&mut self, control: u8, value: u8) -> Option<(ParameterKind, u16, u16)> {
+        match control {
+            NRPN_MSB => self.select(ParameterKind::NonRegistered, Some(value), None),
+            NRPN_LSB => self.select(ParameterKind::NonRegistered, None, Some(value)),
+            RPN_MSB => self.select(ParameterKind::Registered, Some(value), None),
+            RPN_LSB => self.select(ParameterKind::Registered, None, Some(value)),
+            DATA_ENTRY_MSB => return self.set_value((value as u16) << 7),
+            DATA_ENTRY_LSB => {
+                let (kind, number) = self.selected()?;
+                let previous = self.values.get(&(kind, number)).copied().unwrap_or(0);
+                return self.set_value((previous & !0x7F) | value as u16);
+            }
+            DATA_INCREMENT => return self.step_value(value, true),
+            DATA_DECREMENT => return self.step_value(value, false),
+            _ => {}
+        }
+        None
+    }
+
+    fn select(&mut self, kind: ParameterKind, msb: Option<u8>, lsb: Option<u8>) {
+        if self.kind != Some(kind) {
+            self.number_msb = 0;
+            self.number_lsb = 0;
+        }
+        self.kind = Some(kind);
+        if let Some(msb) = msb {
+            self.number_msb = msb;
+        }
+        if let Some(lsb) = lsb {
+            self.number_lsb = lsb;
+        }
+        // RPN 127/127 is the "Null" parameter, i.e. deselect
+        if kind == ParameterKind::Registered && self.number_msb == 0x7F && self.number_lsb == 0x7F {
+            self.kind = None;
+        }
+    }
+
+    fn selected(&self) -> Option<(ParameterKind, u16)> {
+        let kind = self.kind?;
+        Some((
+            kind,
+            ((self.number_msb as u16) << 7) | self.number_lsb as u16,
+        ))
+    }
+
+    fn set_value(&mut self, value: u16) -> Option<(ParameterKind, u16, u16)> {
+        let (kind, number) = self.selected()?;
+        let value = value.min(MAX_PARAMETER_VALUE);
+        self.values.insert((kind, number), value);
+        Some((kind, number, value))
+    }
+
+    /// Devices disagree on whether the data byte is a step size or ignored;
+    /// treat it as a step size, but always move by at least 1
+    fn step_value(&mut self, step: u8, increase: bool) -> Option<(ParameterKind, u16, u16)> {
+        let (kind, number) = self.selected()?;
+        let step = step.max(1) as u16;
+        let previous = self.values.get(&(kind, number)).copied().unwrap_or(0);
+        let value = if increase {
+            previous.saturating_add(step)
+        } else {
+            previous.saturating_sub(step)
+        };
+        self.set_value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(control: u8, value: u8) -> ControlChange {
+        ControlChange::Undefined { control, value }
+    }
+
+    #[test]
+    fn nothing_changes_until_a_parameter_is_selected() {
+        let mut state = ParameterState::default();
+        assert_eq!(state.apply(&ControlChange::DataEntry(100)), None);
+        assert_eq!(state.apply(&ControlChange::DataIncrement(1)), None);
+        assert_eq!(state.apply(&cc(DATA_ENTRY_LSB, 1)), None);
+    }
+
+    #[test]
+    fn data_entry_lsb_replaces_the_low_bits() {
+        let mut state = ParameterState::default();
+        assert_eq!(state.apply(&cc(NRPN_MSB, 1)), None);
+        assert_eq!(state.apply(&cc(NRPN_LSB, 2)), None);
+        let number = (1 << 7) | 2;
+        assert_eq!(
+            state.apply(&ControlChange::DataEntry(10 << 7)),
+            Some((ParameterKind::NonRegistered, number, 1280))
+        );
+        assert_eq!(
+            state.apply(&cc(DATA_ENTRY_LSB, 5)),
+            Some((ParameterKind::NonRegistered, number, 1285))
+        );
+        assert_eq!(
+            state.apply(&cc(DATA_ENTRY_LSB, 7)),
+            Some((ParameterKind::NonRegistered, number, 1287))
+        );
+    }
+
+    #[test]
+    fn increment_and_decrement_step_and_saturate() {
+        let mut state = ParameterState::default();
+        state.apply(&cc(RPN_MSB, 0));
+        state.apply(&cc(RPN_LSB, 0));
+        let pitch_bend_range = |value| Some((ParameterKind::Registered, 0, value));
+        assert_eq!(
+            state.apply(&ControlChange::DataIncrement(0)),
+            pitch_bend_range(1)
+        );
+        assert_eq!(
+            state.apply(&ControlChange::DataIncrement(10)),
+            pitch_bend_range(11)
+        );
+        assert_eq!(
+            state.apply(&ControlChange::DataDecrement(3)),
+            pitch_bend_range(8)
+        );
+        assert_eq!(
+            state.apply(&ControlChange::DataDecrement(100)),
+            pitch_bend_range(0)
+        );
+        state.apply(&ControlChange::DataEntry(MAX_PARAMETER_VALUE));
+        assert_eq!(
+            state.apply(&ControlChange::DataIncrement(5)),
+            pitch_bend_range(MAX_PARAMETER_VALUE)
+        );
+    }
+
+    #[test]
+    fn values_are_kept_per_parameter_and_null_deselects() {
+        let mut state = ParameterState::default();
+        state.apply(&cc(NRPN_MSB, 0));
+        state.apply(&cc(NRPN_LSB, 1));
+        state.apply(&ControlChange::DataEntry(500));
+        state.apply(&cc(NRPN_LSB, 2));
+        assert_eq!(
+            state.apply(&ControlChange::DataIncrement(1)),
+            Some((ParameterKind::NonRegistered, 2, 1))
+        );
+        state.apply(&cc(NRPN_LSB, 1));
+        assert_eq!(
+            state.apply(&ControlChange::DataIncrement(1)),
+            Some((ParameterKind::NonRegistered, 1, 501))
+        );
+
+        state.apply(&cc(RPN_MSB, 127));
+        state.apply(&cc(RPN_LSB, 127));
+        assert_eq!(state.apply(&ControlChange::DataIncrement(1)), None);
+    }
+
+    #[test]
+    fn selection_is_recognised() {
+        assert!(is_parameter_selection(&cc(NRPN_MSB, 0)));
+        assert!(is_parameter_selection(&cc(RPN_LSB, 0)));
+        assert!(!is_parameter_selection(&cc(DATA_ENTRY_LSB, 0)));
+        assert!(!is_parameter_selection(&cc(102, 0)));
+    }
+
+    #[test]
+    fn data_entry_is_taken_from_a_combined_selection() {
+        use midi_msg::Parameter;
+
+        let combined = ControlChange::Parameter(Parameter::PitchBendSensitivityEntry(2, 50));
+        assert_eq!(
+            merged_data_entry(&combined),
+            Some(ControlChange::DataEntry((2 << 7) | 50))
+        );
+        let selection = ControlChange::Parameter(Parameter::PitchBendSensitivity);
+        assert_eq!(merged_data_entry(&selection), None);
+        assert_eq!(merged_data_entry(&ControlChange::DataEntry(100)), None);
+    }
+}
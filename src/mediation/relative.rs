@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+
+use super::{messages::MidiValue, MAX_HIRES, MAX_LORES};
+
+/// How an "endless" encoder expresses a change as a controller value. The
+/// descriptions are for 7-bit values; 14-bit values use the same scheme
+/// scaled up (e.g. binary offset is centred on 8192).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum RelativeEncoding {
+    /// 1..63 increase, 127..64 decrease (127 = -1)
+    #[default]
+    TwosComplement,
+    /// 64 = no change; 65..127 increase, 63..0 decrease (63 = -1)
+    BinaryOffset,
+    /// Bit 6 is the sign: 1..63 increase, 65..127 decrease (65 = -1)
+    SignMagnitude,
+    /// A pair of buttons: any non-zero value on the knob's `controller`
+    /// steps up by one, and on its `decrement` controller steps down by one
+    IncDecButtons,
+}
+
+impl RelativeEncoding {
+    /// All encodings, e.g. for the GUI
+    pub const ALL: [RelativeEncoding; 4] = [
+        RelativeEncoding::TwosComplement,
+        RelativeEncoding::BinaryOffset,
+        RelativeEncoding::SignMagnitude,
+        RelativeEncoding::IncDecButtons,
+    ];
+
+    /// Apply the change encoded in `value` to the previously known absolute
+    /// value (zero if there is none), clamping to the valid range. The
+    /// resolution of the result follows `value`; switches are passed through
+    /// unless they are acting as increment/decrement buttons.
+    pub fn apply(
+        &self,
+        previous: Option<&MidiValue>,
+        value: &MidiValue,
+        is_decrement: bool,
+    ) -> MidiValue {
+        match value {
+            MidiValue::LowRes(x) => {
+                let previous = previous.map(low_res).unwrap_or(0) as i32;
+                let change = self.decode(*x as i32, 7, is_decrement);
+                MidiValue::LowRes((previous + change).clamp(0, MAX_LORES as i32) as u8)
+            }
+            MidiValue::HighRes(x) => {
+                let previous = previous.map(high_res).unwrap_or(0) as i32;
+                let change = self.decode(*x as i32, 14, is_decrement);
+                MidiValue::HighRes((previous + change).clamp(0, MAX_HIRES as i32) as u16)
+            }
+            MidiValue::Switch(on) => match self {
+                RelativeEncoding::IncDecButtons => self.apply(
+                    previous,
+                    &MidiValue::LowRes(if *on { MAX_LORES } else { 0 }),
+                    is_decrement,
+                ),
+                _ => MidiValue::Switch(*on),
+            },
+        }
+    }
+
+    /// Signed change for a value of the given bit width
+    fn decode(&self, value: i32, bits: u32, is_decrement: bool) -> i32 {
+        let half = 1 << (bits - 1);
+        match self {
+            RelativeEncoding::TwosComplement => {
+                if value < half {
+                    value
+                } else {
+                    value - (half << 1)
+                }
+            }
+            RelativeEncoding::BinaryOffset => value - half,
+            RelativeEncoding::SignMagnitude => {
+                if value & half != 0 {
+                    -(value & (half - 1))
+                } else {
+                    value
+                }
+            }
+            RelativeEncoding::IncDecButtons => {
+                // One step is one 7-bit step, whatever the resolution
+                let step = 1 << (bits - 7);
+                match (value, is_decrement) {
+                    (0, _) => 0,
+                    (_, true) => -step,
+                    (_, false) => step,
+                }
+            }
+        }
+    }
+}
+
+fn low_res(value: &MidiValue) -> u8 {
+    match value {
+        MidiValue::LowRes(x) => *x,
+        MidiValue::HighRes(x) => (*x >> 7) as u8,
+        MidiValue::Switch(on) => {
+            if *on {
+                MAX_LORES
+            } else {
+                0
+            }
+        }
+    }
+}
+
+fn high_res(value: &MidiValue) -> u16 {
+    match value {
+        MidiValue::LowRes(x) => (*x as u16) << 7,
+        MidiValue::HighRes(x) => *x,
+        MidiValue::Switch(on) => {
+            if *on {
+                MAX_HIRES
+            } else {
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MidiValue::{HighRes, LowRes, Switch};
+    use RelativeEncoding::*;
+
+    #[test]
+    fn low_res_encodings() {
+        let previous = Some(LowRes(10));
+        let table = [
+            (TwosComplement, 1, 11),
+            (TwosComplement, 5, 15),
+            (TwosComplement, 127, 9),
+            (TwosComplement, 123, 5),
+            (TwosComplement, 64, 0),
+            (BinaryOffset, 65, 11),
+            (BinaryOffset, 64, 10),
+            (BinaryOffset, 63, 9),
+            (BinaryOffset, 0, 0),
+            (SignMagnitude, 1, 11),
+            (SignMagnitude, 65, 9),
+            (SignMagnitude, 70, 4),
+            (SignMagnitude, 64, 10),
+        ];
+        for (encoding, value, expected) in table {
+            assert_eq!(
+                encoding.apply(previous.as_ref(), &LowRes(value), false),
+                LowRes(expected),
+                "{:?} {}",
+                encoding,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn results_are_clamped_and_start_from_zero() {
+        assert_eq!(TwosComplement.apply(None, &LowRes(3), false), LowRes(3));
+        assert_eq!(TwosComplement.apply(None, &LowRes(127), false), LowRes(0));
+        assert_eq!(
+            TwosComplement.apply(Some(&LowRes(126)), &LowRes(5), false),
+            LowRes(127)
+        );
+        assert_eq!(
+            BinaryOffset.apply(Some(&HighRes(MAX_HIRES - 1)), &HighRes(8200), false),
+            HighRes(MAX_HIRES)
+        );
+    }
+
+    #[test]
+    fn high_res_encodings() {
+        let previous = Some(HighRes(1000));
+        let table = [
+            (TwosComplement, 1, 1001),
+            (TwosComplement, 16383, 999),
+            (BinaryOffset, 8193, 1001),
+            (BinaryOffset, 8191, 999),
+            (SignMagnitude, 2, 1002),
+            (SignMagnitude, 8192 + 2, 998),
+        ];
+        for (encoding, value, expected) in table {
+            assert_eq!(
+                encoding.apply(previous.as_ref(), &HighRes(value), false),
+                HighRes(expected),
+                "{:?} {}",
+                encoding,
+                value
+            );
+        }
+        // A 7-bit previous value is scaled up
+        assert_eq!(
+            TwosComplement.apply(Some(&LowRes(10)), &HighRes(1), false),
+            HighRes(1281)
+        );
+    }
+
+    #[test]
+    fn inc_dec_buttons() {
+        let previous = Some(LowRes(10));
+        assert_eq!(
+            IncDecButtons.apply(previous.as_ref(), &LowRes(127), false),
+            LowRes(11)
+        );
+        assert_eq!(
+            IncDecButtons.apply(previous.as_ref(), &LowRes(127), true),
+            LowRes(9)
+        );
+        assert_eq!(
+            IncDecButtons.apply(previous.as_ref(), &LowRes(0), false),
+            LowRes(10)
+        );
+        assert_eq!(
+            IncDecButtons.apply(previous.as_ref(), &Switch(true), false),
+            LowRes(11)
+        );
+        assert_eq!(
+            IncDecButtons.apply(Some(&HighRes(1000)), &HighRes(1), true),
+            HighRes(872)
+        );
+        // Other encodings pass switches through
+        assert_eq!(
+            TwosComplement.apply(previous.as_ref(), &Switch(true), false),
+            Switch(true)
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::messages::ControlChangePayload;
+
+/// Closer than this (as a fraction of travel), a smoothed knob snaps to its target
+const SETTLED: f32 = 0.0005;
+
+/// Smoothing applied to a knob's position, so that coarse or jumpy
+/// controllers give gliding output
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Eases towards each new position; the time constant in milliseconds
+    /// (about two thirds of the way there after this long)
+    OnePole(f32),
+    /// Moves towards each new position at no more than this fraction of the
+    /// knob's travel per second
+    Slew(f32),
+}
+
+impl Smoothing {
+    /// Move `current` towards `target`, for this much elapsed time
+    pub fn step(&self, current: f32, target: f32, elapsed: Duration) -> f32 {
+        let seconds = elapsed.as_secs_f32();
+        match self {
+            Smoothing::OnePole(time_constant_ms) => {
+                if *time_constant_ms <= 0.0 {
+                    return target;
+                }
+                let amount = 1.0 - (-seconds * 1000.0 / time_constant_ms).exp();
+                current + (target - current) * amount
+            }
+            Smoothing::Slew(per_second) => {
+                if *per_second <= 0.0 {
+                    return target;
+                }
+                let max_change = per_second * seconds;
+                current + (target - current).clamp(-max_change, max_change)
+            }
+        }
+    }
+}
+
+/// Output of a knob that is smoothed and/or rate limited, advanced on a timer
+/// rather than published directly on MIDI input
+pub struct KnobOutput {
+    /// Normalised position (0.0-1.0) from the latest input
+    pub target: f32,
+    /// Normalised position, after smoothing
+    pub current: f32,
+    last_step: Instant,
+    last_published: Option<Instant>,
+    published: Option<f32>,
+    /// The latest Control Change for this knob not yet published, if rate limited
+    pub pending_control_change: Option<ControlChangePayload>,
+}
+
+impl KnobOutput {
+    pub fn new(position: f32, now: Instant) -> Self {
+        KnobOutput {
+            target: position,
+            current: position,
+            last_step: now,
+            last_published: None,
+            published: None,
+            pending_control_change: None,
+        }
+    }
+
+    pub fn set_target(&mut self, position: f32, now: Instant) {
+        if self.is_settled() {
+            // Don't count the time spent idle
+            self.last_step = now;
+        }
+        self.target = position;
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.current == self.target
+    }
+
+    /// Whether anything is still to be published: the position hasn't
+    /// settled, or hasn't been published since (e.g. held back by the rate
+    /// limit), or a Control Change is waiting
+    pub fn needs_update(&self) -> bool {
+        !self.is_settled()
+            || self.published != Some(self.current)
+            || self.pending_control_change.is_some()
+    }
+
+    /// Apply smoothing up to `now`
+    pub fn step(&mut self, smoothing: Option<Smoothing>, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_step);
+        self.last_step = now;
+        self.current = match smoothing {
+            Some(smoothing) => smoothing.step(self.current, self.target, elapsed),
+            None => self.target,
+        };
+        if (self.current - self.target).abs() < SETTLED {
+            self.current = self.target;
+        }
+    }
+
+    /// Whether anything may be published now, given the minimum interval
+    pub fn is_due(&self, rate_limit: Option<Duration>, now: Instant) -> bool {
+        match (rate_limit, self.last_published) {
+            (Some(limit), Some(last)) => now.saturating_duration_since(last) >= limit,
+            _ => true,
+        }
+    }
+
+    /// The position to publish, if it has changed since it was last published
+    pub fn take_unpublished(&mut self) -> Option<f32> {
+        if self.published == Some(self.current) {
+            None
+        } else {
+            self.published = Some(self.current);
+            Some(self.current)
+        }
+    }
+
+    pub fn mark_published(&mut self, now: Instant) {
+        self.last_published = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slew_limits_change_per_second() {
+        let slew = Smoothing::Slew(0.5);
+        let next = slew.step(0.0, 1.0, Duration::from_millis(100));
+        assert!((next - 0.05).abs() < 1e-6);
+        assert_eq!(slew.step(0.98, 1.0, Duration::from_millis(100)), 1.0);
+    }
+
+    #[test]
+    fn rate_limited_output_waits_for_interval() {
+        let start = Instant::now();
+        let limit = Some(Duration::from_millis(50));
+        let mut output = KnobOutput::new(0.0, start);
+        assert!(output.is_due(limit, start));
+        output.mark_published(start);
+        output.set_target(1.0, start);
+        assert!(!output.is_due(limit, start + Duration::from_millis(20)));
+        assert!(output.is_due(limit, start + Duration::from_millis(50)));
+        output.step(None, start + Duration::from_millis(50));
+        assert_eq!(output.take_unpublished(), Some(1.0));
+        assert_eq!(output.take_unpublished(), None);
+    }
+
+    #[test]
+    fn smoothed_and_rate_limited_output_ends_on_target() {
+        let start = Instant::now();
+        let smoothing = Some(Smoothing::OnePole(20.0));
+        let limit = Some(Duration::from_millis(50));
+        let mut output = KnobOutput::new(0.0, start);
+        output.set_target(1.0, start);
+        let mut last_published = None;
+        // Updated every 10 ms, as from the main loop, until nothing is left
+        for tick in 0..100 {
+            if !output.needs_update() {
+                break;
+            }
+            let now = start + Duration::from_millis(tick * 10);
+            output.step(smoothing, now);
+            if output.is_due(limit, now) {
+                if let Some(position) = output.take_unpublished() {
+                    output.mark_published(now);
+                    last_published = Some(position);
+                }
+            }
+        }
+        assert!(!output.needs_update());
+        assert_eq!(last_published, Some(1.0));
+    }
+}
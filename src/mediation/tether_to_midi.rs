@@ -0,0 +1,233 @@
+use anyhow::anyhow;
+use midi_msg::{ChannelVoiceMsg, ControlChange, MidiMsg};
+
+use super::{
+    controllers::{channel_mode_from_label, control_change_from_label, high_res_numbered},
+    int_to_channel,
+    messages::{
+        ChannelPressurePayload, ControlChangePayload, ControllerLabel, MidiValue, NotePayload,
+        PitchBendPayload, PolyPressurePayload,
+    },
+};
+
+pub fn note_on_to_midi(payload: &NotePayload) -> anyhow::Result<MidiMsg> {
+    Ok(MidiMsg::ChannelVoice {
+        channel: int_to_channel(payload.channel)?,
+        msg: ChannelVoiceMsg::NoteOn {
+            note: payload.note,
+            velocity: payload.velocity,
+        },
+    })
+}
+
+pub fn note_off_to_midi(payload: &NotePayload) -> anyhow::Result<MidiMsg> {
+    Ok(MidiMsg::ChannelVoice {
+        channel: int_to_channel(payload.channel)?,
+        msg: ChannelVoiceMsg::NoteOff {
+            note: payload.note,
+            velocity: payload.velocity,
+        },
+    })
+}
+
+pub fn control_change_to_midi(payload: &ControlChangePayload) -> anyhow::Result<MidiMsg> {
+    let control = match &payload.controller {
+        ControllerLabel::Numbered(control) => match payload.value {
+            MidiValue::LowRes(value) => ControlChange::Undefined {
+                control: *control,
+                value,
+            },
+            MidiValue::HighRes(value) => {
+                if *control < 32 {
+                    high_res_numbered(*control, value)
+                } else {
+                    ControlChange::Undefined {
+                        control: *control,
+                        value: (value >> 7) as u8,
+                    }
+                }
+            }
+            MidiValue::Switch(on) => ControlChange::Undefined {
+                control: *control,
+                value: if on { 127 } else { 0 },
+            },
+        },
+        ControllerLabel::Special(label) => {
+            if let Some(msg) = channel_mode_from_label(label, &payload.value) {
+                return Ok(MidiMsg::ChannelMode {
+                    channel: int_to_channel(payload.channel)?,
+                    msg,
+                });
+            }
+            control_change_from_label(label, &payload.value)
+                .ok_or(anyhow!("Unknown controller label \"{}\"", label))?
+        }
+    };
+    Ok(MidiMsg::ChannelVoice {
+        channel: int_to_channel(payload.channel)?,
+        msg: ChannelVoiceMsg::ControlChange { control },
+    })
+}
+
+/// Only the raw `value` is used; `normalised` is ignored
+pub fn pitch_bend_to_midi(payload: &PitchBendPayload) -> anyhow::Result<MidiMsg> {
+    Ok(MidiMsg::ChannelVoice {
+        channel: int_to_channel(payload.channel)?,
+        msg: ChannelVoiceMsg::PitchBend {
+            bend: payload.value,
+        },
+    })
+}
+
+pub fn channel_pressure_to_midi(payload: &ChannelPressurePayload) -> anyhow::Result<MidiMsg> {
+    Ok(MidiMsg::ChannelVoice {
+        channel: int_to_channel(payload.channel)?,
+        msg: ChannelVoiceMsg::ChannelPressure {
+            pressure: payload.pressure,
+        },
+    })
+}
+
+pub fn poly_pressure_to_midi(payload: &PolyPressurePayload) -> anyhow::Result<MidiMsg> {
+    Ok(MidiMsg::ChannelVoice {
+        channel: int_to_channel(payload.channel)?,
+        msg: ChannelVoiceMsg::PolyPressure {
+            note: payload.note,
+            pressure: payload.pressure,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(channel: u8, note: u8, velocity: u8) -> NotePayload {
+        NotePayload {
+            channel,
+            note,
+            velocity,
+            name: String::new(),
+            pitch_class: 0,
+            octave: 0,
+            frequency: 0.0,
+        }
+    }
+
+    fn control_change(channel: u8, controller: ControllerLabel, value: MidiValue) -> Vec<u8> {
+        control_change_to_midi(&ControlChangePayload {
+            channel,
+            controller,
+            value,
+        })
+        .unwrap()
+        .to_midi()
+    }
+
+    #[test]
+    fn notes() {
+        assert_eq!(
+            note_on_to_midi(&note(1, 60, 100)).unwrap().to_midi(),
+            vec![0x90, 60, 100]
+        );
+        assert_eq!(
+            note_off_to_midi(&note(16, 61, 0)).unwrap().to_midi(),
+            vec![0x8F, 61, 0]
+        );
+        // Clamped to 7 bits
+        assert_eq!(
+            note_on_to_midi(&note(2, 200, 255)).unwrap().to_midi(),
+            vec![0x91, 127, 127]
+        );
+        assert!(note_on_to_midi(&note(0, 60, 100)).is_err());
+        assert!(note_off_to_midi(&note(17, 60, 0)).is_err());
+    }
+
+    #[test]
+    fn numbered_control_changes() {
+        use ControllerLabel::Numbered;
+        use MidiValue::{HighRes, LowRes, Switch};
+
+        let table = [
+            (Numbered(20), LowRes(5), vec![0xB0, 20, 5]),
+            (Numbered(20), LowRes(200), vec![0xB0, 20, 127]),
+            (Numbered(20), HighRes(1000), vec![0xB0, 20, 7, 52, 104]),
+            // Only controllers below 32 have an LSB
+            (Numbered(102), HighRes(1000), vec![0xB0, 102, 7]),
+            (Numbered(102), Switch(true), vec![0xB0, 102, 127]),
+            (Numbered(102), Switch(false), vec![0xB0, 102, 0]),
+        ];
+        for (controller, value, expected) in table {
+            assert_eq!(
+                control_change(1, controller.clone(), value.clone()),
+                expected,
+                "{:?} {:?}",
+                controller,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn special_control_changes() {
+        let special = |label: &str| ControllerLabel::Special(label.into());
+        assert_eq!(
+            control_change(3, special("Volume"), MidiValue::HighRes(1000)),
+            vec![0xB2, 7, 7, 39, 104]
+        );
+        assert_eq!(
+            control_change(1, special("Hold"), MidiValue::Switch(true)),
+            vec![0xB0, 64, 127]
+        );
+        assert_eq!(
+            control_change(1, special("GeneralPurpose8"), MidiValue::LowRes(9)),
+            vec![0xB0, 83, 9]
+        );
+        assert_eq!(
+            control_change(1, special("AllNotesOff"), MidiValue::Switch(true)),
+            vec![0xB0, 123, 0]
+        );
+        assert!(control_change_to_midi(&ControlChangePayload {
+            channel: 1,
+            controller: special("NoSuchController"),
+            value: MidiValue::LowRes(0),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn pitch_bend_and_pressure() {
+        let bend = |value| {
+            pitch_bend_to_midi(&PitchBendPayload {
+                channel: 1,
+                value,
+                normalised: 0.0,
+            })
+            .unwrap()
+            .to_midi()
+        };
+        assert_eq!(bend(8192), vec![0xE0, 0, 64]);
+        assert_eq!(bend(0), vec![0xE0, 0, 0]);
+        // Clamped to 14 bits
+        assert_eq!(bend(20000), vec![0xE0, 127, 127]);
+        assert_eq!(
+            channel_pressure_to_midi(&ChannelPressurePayload {
+                channel: 4,
+                pressure: 90,
+            })
+            .unwrap()
+            .to_midi(),
+            vec![0xD3, 90]
+        );
+        assert_eq!(
+            poly_pressure_to_midi(&PolyPressurePayload {
+                channel: 1,
+                note: 60,
+                pressure: 200,
+            })
+            .unwrap()
+            .to_midi(),
+            vec![0xA0, 60, 127]
+        );
+    }
+}
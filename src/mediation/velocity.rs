@@ -0,0 +1,156 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{mappings::filters_match, MAX_LORES};
+
+/// Response applied to NoteOn velocities (1-127)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    /// Louder for a light touch
+    Soft,
+    /// Needs a heavier touch to get loud
+    Hard,
+    /// Every note gets this velocity
+    Fixed(u8),
+    /// Output velocities for inputs spread evenly from 0 to 127, with
+    /// values in between interpolated, e.g. [0, 100, 127]
+    Table(Vec<u8>),
+}
+
+impl VelocityCurve {
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let x = velocity.min(MAX_LORES) as f32 / MAX_LORES as f32;
+        let y = match self {
+            VelocityCurve::Linear => return velocity,
+            VelocityCurve::Fixed(fixed) => return (*fixed).min(MAX_LORES),
+            VelocityCurve::Soft => x.sqrt(),
+            VelocityCurve::Hard => x * x,
+            VelocityCurve::Table(table) => match table.len() {
+                0 => return velocity,
+                1 => return table[0].min(MAX_LORES),
+                len => {
+                    let position = x * (len - 1) as f32;
+                    let i = (position.floor() as usize).min(len - 2);
+                    let fraction = position - i as f32;
+                    let (a, b) = (table[i] as f32, table[i + 1] as f32);
+                    (a + (b - a) * fraction) / MAX_LORES as f32
+                }
+            },
+        };
+        (y * MAX_LORES as f32).round().clamp(0.0, MAX_LORES as f32) as u8
+    }
+}
+
+/// How notes from matching ports and channels are treated before being
+/// published
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NoteProfile {
+    /// Only apply to ports whose name contains this text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    /// Only apply to this MIDI channel (1-16)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+    #[serde(default)]
+    pub curve: VelocityCurve,
+    /// Notes played softer than this (before the curve) are dropped, along
+    /// with their NoteOff
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_velocity: Option<u8>,
+    /// Notes outside this range (MIDI note numbers, inclusive) are dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lowest_note: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highest_note: Option<u8>,
+}
+
+impl NoteProfile {
+    pub fn matches(&self, port_name: &str, channel: u8) -> bool {
+        filters_match(self.channel, self.port.as_deref(), port_name, channel)
+    }
+
+    pub fn in_range(&self, note: u8) -> bool {
+        self.lowest_note.is_none_or(|lowest| note >= lowest)
+            && self.highest_note.is_none_or(|highest| note <= highest)
+    }
+
+    /// The velocity to publish a NoteOn with, or None if it should be dropped
+    pub fn note_on(&self, note: u8, velocity: u8) -> Option<u8> {
+        if !self.in_range(note) || self.min_velocity.is_some_and(|min| velocity < min) {
+            return None;
+        }
+        // Never turn a note into a NoteOff
+        Some(self.curve.apply(velocity).max(1))
+    }
+}
+
+/// The first profile matching this port and channel, if any
+pub fn find_note_profile<'a>(
+    profiles: &'a [NoteProfile],
+    port_name: &str,
+    channel: u8,
+) -> Option<&'a NoteProfile> {
+    profiles
+        .iter()
+        .find(|profile| profile.matches(port_name, channel))
+}
+
+/// A profile file is a list of profiles; the first matching a note's port and
+/// channel is applied
+pub fn load_note_profiles(path: &Path) -> anyhow::Result<Vec<NoteProfile>> {
+    let json_str = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read note profile file {}", path.display()))?;
+    serde_json::from_str(&json_str)
+        .with_context(|| format!("Invalid note profile file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_keep_the_ends() {
+        for curve in [
+            VelocityCurve::Linear,
+            VelocityCurve::Soft,
+            VelocityCurve::Hard,
+            VelocityCurve::Table(vec![0, 100, 127]),
+        ] {
+            assert_eq!(curve.apply(0), 0, "{:?}", curve);
+            assert_eq!(curve.apply(127), 127, "{:?}", curve);
+        }
+        assert!(VelocityCurve::Soft.apply(64) > 64);
+        assert!(VelocityCurve::Hard.apply(64) < 64);
+        assert_eq!(VelocityCurve::Table(vec![0, 100, 127]).apply(64), 100);
+        assert_eq!(VelocityCurve::Fixed(90).apply(10), 90);
+    }
+
+    #[test]
+    fn velocities_stay_in_range() {
+        assert_eq!(VelocityCurve::Fixed(200).apply(10), 127);
+        assert_eq!(VelocityCurve::Table(vec![200]).apply(10), 127);
+        assert_eq!(VelocityCurve::Table(vec![0, 255]).apply(127), 127);
+    }
+
+    #[test]
+    fn ghost_notes_and_out_of_range_notes_are_dropped() {
+        let profile = NoteProfile {
+            port: None,
+            channel: Some(10),
+            curve: VelocityCurve::Hard,
+            min_velocity: Some(10),
+            lowest_note: Some(36),
+            highest_note: Some(51),
+        };
+        assert!(profile.matches("Drum Pads", 10));
+        assert!(!profile.matches("Drum Pads", 1));
+        assert_eq!(profile.note_on(40, 5), None);
+        assert_eq!(profile.note_on(52, 100), None);
+        assert_eq!(profile.note_on(36, 10), Some(1));
+        assert_eq!(profile.note_on(51, 127), Some(127));
+    }
+}
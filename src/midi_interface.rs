@@ -1,7 +1,8 @@
 use std::{error::Error, sync::mpsc, time::Duration};
 
+use log::{debug, error};
 use midi_msg::{MidiMsg, ReceiverContext, SystemRealTimeMsg};
-use midir::{MidiInput, MidiInputPort};
+use midir::{MidiInput, MidiInputPort, MidiOutput, MidiOutputConnection};
 
 pub fn get_midi_connection(
     midi_in: &MidiInput,
@@ -56,3 +57,54 @@ pub fn midi_listener_thread(
         //     listen_for_midi(port, midi_tx);
     })
 }
+
+/// Connect to a hardware MIDI output, specified either by index or by
+/// (part of) the port name
+pub fn get_midi_output_connection(
+    midi_out: MidiOutput,
+    preferred_port: &str,
+) -> Result<(MidiOutputConnection, String), Box<dyn Error>> {
+    let out_ports = midi_out.ports();
+    let out_port = match preferred_port.parse::<usize>() {
+        Ok(index) => out_ports.get(index),
+        Err(_) => out_ports.iter().find(|p| {
+            midi_out
+                .port_name(p)
+                .map(|name| name.contains(preferred_port))
+                .unwrap_or(false)
+        }),
+    }
+    .ok_or(format!(
+        "No MIDI output port matching \"{}\"",
+        preferred_port
+    ))?;
+
+    let out_port_name = midi_out.port_name(out_port)?;
+    let connection = midi_out.connect(out_port, "midir-write-output")?;
+
+    Ok((connection, out_port_name))
+}
+
+/// Create a virtual MIDI output port which other applications can connect to
+#[cfg(unix)]
+pub fn create_virtual_midi_output(
+    midi_out: MidiOutput,
+    port_name: &str,
+) -> Result<MidiOutputConnection, Box<dyn Error>> {
+    use midir::os::unix::VirtualOutput;
+    Ok(midi_out.create_virtual(port_name)?)
+}
+
+pub fn midi_sender_thread(
+    mut connection: MidiOutputConnection,
+    midi_out_rx: mpsc::Receiver<MidiMsg>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        while let Ok(msg) = midi_out_rx.recv() {
+            debug!("Sending MIDI message: {:?}", &msg);
+            if let Err(e) = connection.send(&msg.to_midi()) {
+                error!("Failed to send MIDI message: {}", e);
+            }
+        }
+    })
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -27,15 +29,74 @@ pub struct Cli {
     #[arg(long = "tether.id")]
     pub tether_id: Option<String>,
 
-    /// Enable translation of relative controller values into absolute values
+    /// Enable translation of relative controller values into absolute values,
+    /// for all ports (unless a knob mapping says otherwise)
     #[arg(long = "midi.relative")]
     pub relative_mode_enabled: bool,
 
+    /// Enable relative mode only for these port indexes, separated by commas,
+    /// e.g. `--midi.relativePorts 1,3`
+    #[arg(long = "midi.relativePorts", value_delimiter = ',')]
+    pub relative_ports: Vec<usize>,
+
     /// Disable lookup of "knob mapping" for device(s)
     #[arg(long = "knobs.disable", default_value_t = false)]
     pub knobs_disable: bool,
 
+    /// Knob mapping file, or directory of .json mapping files, to merge with
+    /// (and override) the built-in mappings and those in the user config
+    /// directory
+    #[arg(long = "mappings")]
+    pub mappings: Option<PathBuf>,
+
+    /// Number knobs continuously across all mapped devices (in port order),
+    /// unless a device's mapping gives its own index offset
+    #[arg(long = "knobs.continuous")]
+    pub knobs_continuous: bool,
+
+    /// Octave number for middle C (MIDI note 60) in note names, usually
+    /// 4 ("C4") but 3 for some manufacturers ("C3"); from -2 to 8
+    #[arg(
+        long = "notes.middleC",
+        default_value_t = 4,
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i8).range(-2..=8)
+    )]
+    pub middle_c_octave: i8,
+
+    /// Reference frequency (Hz) for A4, used for note frequencies
+    #[arg(long = "notes.a4", default_value_t = 440.0, value_parser = parse_frequency)]
+    pub a4_frequency: f32,
+
+    /// Note profile file (JSON), with velocity curves, ghost note thresholds
+    /// and note ranges per port and/or channel
+    #[arg(long = "notes.profile")]
+    pub notes_profile: Option<PathBuf>,
+
+    /// Enable recognition of chords from held notes, published on the
+    /// "chord" plug
+    #[arg(long = "chords")]
+    pub chords_enabled: bool,
+
+    /// Send MIDI translated from incoming Tether messages to this output
+    /// port, specified by index or (part of) its name
+    #[arg(long = "midi.output")]
+    pub midi_output: Option<String>,
+
+    /// Create a virtual MIDI output port with this name, for incoming
+    /// Tether messages to be translated into (not supported on Windows)
+    #[arg(long = "midi.virtualOutput", conflicts_with = "midi_output")]
+    pub midi_virtual_output: Option<String>,
+
     /// Specify one or more MIDI ports by index, in any order
     #[clap()]
     pub midi_ports: Vec<usize>,
 }
+
+fn parse_frequency(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(frequency) if frequency > 0.0 && frequency.is_finite() => Ok(frequency),
+        Ok(_) => Err(String::from("must be a frequency above 0")),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -4,10 +4,24 @@ use std::{
     time::Duration,
 };
 
-use log::debug;
-use tether_agent::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+use log::{debug, warn};
+use midi_msg::MidiMsg;
+use rmp_serde::from_slice;
+use tether_agent::{
+    three_part_topic::TetherOrCustomTopic, PlugDefinition, PlugOptionsBuilder,
+    TetherAgentOptionsBuilder,
+};
 
-use crate::mediation::messages::TetherMidiMessage;
+use crate::mediation::{
+    messages::{
+        ChannelPressurePayload, ControlChangePayload, ControllerValuesRequest, NotePayload,
+        PitchBendPayload, PolyPressurePayload, SetKnobRequest, TetherCommand, TetherMidiMessage,
+    },
+    tether_to_midi::{
+        channel_pressure_to_midi, control_change_to_midi, note_off_to_midi, note_on_to_midi,
+        pitch_bend_to_midi, poly_pressure_to_midi,
+    },
+};
 
 #[derive(Clone)]
 pub struct TetherSettings {
@@ -16,11 +30,23 @@ pub struct TetherSettings {
     pub id: Option<String>,
 }
 
+struct MidiOutputPlugs {
+    note_on: PlugDefinition,
+    note_off: PlugDefinition,
+    control_change: PlugDefinition,
+    pitch_bend: PlugDefinition,
+    channel_pressure: PlugDefinition,
+    poly_pressure: PlugDefinition,
+    midi_out_tx: Sender<MidiMsg>,
+}
+
 pub type TetherStateMessage = (bool, TetherSettings, Option<String>);
 pub fn start_tether_agent(
     rx: Receiver<TetherMidiMessage>,
     tx: Sender<TetherStateMessage>,
     settings: TetherSettings,
+    midi_out_tx: Option<Sender<MidiMsg>>,
+    command_tx: Sender<TetherCommand>,
 ) -> JoinHandle<()> {
     let mut agent = TetherAgentOptionsBuilder::new(&settings.role)
         .host(Some(&settings.host))
@@ -38,20 +64,117 @@ pub fn start_tether_agent(
         .qos(Some(1))
         .build(&mut agent)
         .expect("failed to create output plug");
+    // Retained, so that late joiners immediately know which notes are down
+    let notes_held_output = PlugOptionsBuilder::create_output("notesHeld")
+        .qos(Some(1))
+        .retain(Some(true))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let chord_output = PlugOptionsBuilder::create_output("chord")
+        .qos(Some(1))
+        .retain(Some(true))
+        .build(&mut agent)
+        .expect("failed to create output plug");
     let cc_output = PlugOptionsBuilder::create_output("controlChange")
         .qos(Some(0))
         .build(&mut agent)
         .expect("failed to create output plug");
+    let pitch_bend_output = PlugOptionsBuilder::create_output("pitchBend")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let channel_pressure_output = PlugOptionsBuilder::create_output("channelPressure")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let poly_pressure_output = PlugOptionsBuilder::create_output("polyPressure")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let program_change_output = PlugOptionsBuilder::create_output("programChange")
+        .qos(Some(1))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let parameter_output = PlugOptionsBuilder::create_output("parameter")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
     let knob_output = PlugOptionsBuilder::create_output("knobs")
         .qos(Some(0))
         .build(&mut agent)
         .expect("failed to create output plug");
+    let button_output = PlugOptionsBuilder::create_output("buttons")
+        .qos(Some(1))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let xy_pad_output = PlugOptionsBuilder::create_output("xyPads")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let fader_bank_output = PlugOptionsBuilder::create_output("faderBanks")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+    let encoder_output = PlugOptionsBuilder::create_output("encoders")
+        .qos(Some(0))
+        .build(&mut agent)
+        .expect("failed to create output plug");
     let raw_output = PlugOptionsBuilder::create_output("raw")
         .qos(Some(0))
         .build(&mut agent)
         .expect("failed to create output plug");
+    let controller_values_output = PlugOptionsBuilder::create_output("controllerValues")
+        .qos(Some(1))
+        .build(&mut agent)
+        .expect("failed to create output plug");
+
+    let reset_controller_values_input = PlugOptionsBuilder::create_input("resetControllerValues")
+        .qos(Some(1))
+        .build(&mut agent)
+        .expect("failed to create input plug");
+    let query_controller_values_input = PlugOptionsBuilder::create_input("queryControllerValues")
+        .qos(Some(1))
+        .build(&mut agent)
+        .expect("failed to create input plug");
+    let set_knobs_input = PlugOptionsBuilder::create_input("setKnobs")
+        .qos(Some(1))
+        .build(&mut agent)
+        .expect("failed to create input plug");
+
+    // Input plugs are only needed when there is somewhere to send the resulting MIDI
+    let midi_output = midi_out_tx.map(|midi_out_tx| MidiOutputPlugs {
+        note_on: PlugOptionsBuilder::create_input("notesOn")
+            .qos(Some(1))
+            .build(&mut agent)
+            .expect("failed to create input plug"),
+        note_off: PlugOptionsBuilder::create_input("notesOff")
+            .qos(Some(1))
+            .build(&mut agent)
+            .expect("failed to create input plug"),
+        control_change: PlugOptionsBuilder::create_input("controlChange")
+            .qos(Some(0))
+            .build(&mut agent)
+            .expect("failed to create input plug"),
+        pitch_bend: PlugOptionsBuilder::create_input("pitchBend")
+            .qos(Some(0))
+            .build(&mut agent)
+            .expect("failed to create input plug"),
+        channel_pressure: PlugOptionsBuilder::create_input("channelPressure")
+            .qos(Some(0))
+            .build(&mut agent)
+            .expect("failed to create input plug"),
+        poly_pressure: PlugOptionsBuilder::create_input("polyPressure")
+            .qos(Some(0))
+            .build(&mut agent)
+            .expect("failed to create input plug"),
+        midi_out_tx,
+    });
+
     std::thread::spawn(move || loop {
-        if let Ok(msg) = rx.recv() {
+        let mut work_done = false;
+
+        while let Ok(msg) = rx.try_recv() {
+            work_done = true;
             debug!("Tether Thread received message via Model: {:?}", &msg);
             match msg {
                 TetherMidiMessage::Raw(payload) => {
@@ -70,11 +193,151 @@ pub fn start_tether_agent(
                         .encode_and_publish(&note_off_output, n_payload)
                         .unwrap();
                 }
+                TetherMidiMessage::NotesHeld(nh_payload) => {
+                    agent
+                        .encode_and_publish(&notes_held_output, nh_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::Chord(c_payload) => {
+                    agent.encode_and_publish(&chord_output, c_payload).unwrap();
+                }
+                TetherMidiMessage::PitchBend(pb_payload) => {
+                    agent
+                        .encode_and_publish(&pitch_bend_output, pb_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::ChannelPressure(cp_payload) => {
+                    agent
+                        .encode_and_publish(&channel_pressure_output, cp_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::PolyPressure(pp_payload) => {
+                    agent
+                        .encode_and_publish(&poly_pressure_output, pp_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::ProgramChange(pc_payload) => {
+                    agent
+                        .encode_and_publish(&program_change_output, pc_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::Parameter(p_payload) => {
+                    agent
+                        .encode_and_publish(&parameter_output, p_payload)
+                        .unwrap();
+                }
                 TetherMidiMessage::Knob(k_payload) => {
                     agent.encode_and_publish(&knob_output, &k_payload).unwrap();
                 }
+                TetherMidiMessage::Button(b_payload) => {
+                    agent.encode_and_publish(&button_output, b_payload).unwrap();
+                }
+                TetherMidiMessage::XyPad(xy_payload) => {
+                    agent
+                        .encode_and_publish(&xy_pad_output, xy_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::FaderBank(fb_payload) => {
+                    agent
+                        .encode_and_publish(&fader_bank_output, fb_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::Encoder(e_payload) => {
+                    agent
+                        .encode_and_publish(&encoder_output, e_payload)
+                        .unwrap();
+                }
+                TetherMidiMessage::ControllerValues(cv_payload) => {
+                    agent
+                        .encode_and_publish(&controller_values_output, cv_payload)
+                        .unwrap();
+                }
             }
-        } else {
+        }
+
+        while let Some((topic, payload)) = agent.check_messages() {
+            work_done = true;
+            let is_reset = reset_controller_values_input.matches(&topic);
+            if is_reset || query_controller_values_input.matches(&topic) {
+                // An empty message means "all ports"
+                let request = if payload.is_empty() {
+                    Ok(ControllerValuesRequest::default())
+                } else {
+                    from_slice::<ControllerValuesRequest>(&payload)
+                };
+                match request {
+                    Ok(request) => command_tx
+                        .send(if is_reset {
+                            TetherCommand::ResetControllerValues(request)
+                        } else {
+                            TetherCommand::QueryControllerValues(request)
+                        })
+                        .expect("failed to send on channel"),
+                    Err(e) => warn!("Failed to decode message on topic {:?}: {}", topic, e),
+                }
+                continue;
+            }
+            if set_knobs_input.matches(&topic) {
+                match from_slice::<SetKnobRequest>(&payload) {
+                    Ok(request) => command_tx
+                        .send(TetherCommand::SetKnob(request))
+                        .expect("failed to send on channel"),
+                    Err(e) => warn!("Failed to decode message on topic {:?}: {}", topic, e),
+                }
+                continue;
+            }
+            let Some(midi_output) = &midi_output else {
+                continue;
+            };
+            // Our own output plugs share names with these input plugs; don't echo them back
+            if let TetherOrCustomTopic::Tether(t) = &topic {
+                if t.role() == agent.role() && t.id() == agent.id() {
+                    continue;
+                }
+            }
+            let midi_msg = if midi_output.note_on.matches(&topic) {
+                from_slice::<NotePayload>(&payload)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|n| note_on_to_midi(&n))
+            } else if midi_output.note_off.matches(&topic) {
+                from_slice::<NotePayload>(&payload)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|n| note_off_to_midi(&n))
+            } else if midi_output.control_change.matches(&topic) {
+                from_slice::<ControlChangePayload>(&payload)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|cc| control_change_to_midi(&cc))
+            } else if midi_output.pitch_bend.matches(&topic) {
+                from_slice::<PitchBendPayload>(&payload)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|pb| pitch_bend_to_midi(&pb))
+            } else if midi_output.channel_pressure.matches(&topic) {
+                from_slice::<ChannelPressurePayload>(&payload)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|cp| channel_pressure_to_midi(&cp))
+            } else if midi_output.poly_pressure.matches(&topic) {
+                from_slice::<PolyPressurePayload>(&payload)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|pp| poly_pressure_to_midi(&pp))
+            } else {
+                continue;
+            };
+            match midi_msg {
+                Ok(midi_msg) => {
+                    debug!("Tether Thread will send MIDI message: {:?}", &midi_msg);
+                    midi_output
+                        .midi_out_tx
+                        .send(midi_msg)
+                        .expect("failed to send on channel");
+                }
+                Err(e) => warn!(
+                    "Failed to translate message on topic {:?} into MIDI: {}",
+                    topic, e
+                ),
+            }
+        }
+
+        if !work_done {
             std::thread::sleep(Duration::from_millis(1));
         }
     })